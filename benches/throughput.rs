@@ -0,0 +1,68 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::fmt::Write;
+use toy_marketplace::{process_reader, PaymentsEngine};
+
+const ROWS: usize = 100_000;
+
+/*
+A csv of `rows` rows spread round robin over `clients` clients. Roughly `dispute_percent`
+of the rows dispute and then resolve the client's latest deposit, the rest are deposits,
+each with a fresh transaction id.
+*/
+fn input(rows: usize, clients: u16, dispute_percent: usize) -> String {
+    let mut csv = String::from("type,client,tx,amount\n");
+    let mut latest_deposit = vec![None; clients as usize + 1];
+    let mut tx = 0u32;
+    let mut row = 0;
+    while row < rows {
+        let client = (row % clients as usize) as u16 + 1;
+        match latest_deposit[client as usize] {
+            Some(target) if row % 100 < dispute_percent && row + 1 < rows => {
+                writeln!(csv, "dispute,{},{},", client, target).unwrap();
+                writeln!(csv, "resolve,{},{},", client, target).unwrap();
+                row += 2;
+            }
+            _ => {
+                tx += 1;
+                writeln!(
+                    csv,
+                    "deposit,{},{},{}.{:04}",
+                    client,
+                    tx,
+                    row % 100,
+                    row % 10_000
+                )
+                .unwrap();
+                latest_deposit[client as usize] = Some(tx);
+                row += 1;
+            }
+        }
+    }
+    csv
+}
+
+fn throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_reader");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS as u64));
+
+    let scenarios = [
+        ("deposit_only", input(ROWS, 100, 0)),
+        ("dispute_heavy", input(ROWS, 100, 60)),
+        // close to every client id, so the client map keeps growing the whole run
+        ("hashmap_growth", input(ROWS, u16::MAX, 0)),
+    ];
+    for (name, csv) in &scenarios {
+        group.bench_with_input(BenchmarkId::from_parameter(name), csv, |b, csv| {
+            b.iter(|| {
+                let mut engine = PaymentsEngine::default();
+                process_reader(csv.as_bytes(), &mut engine).unwrap();
+                engine
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
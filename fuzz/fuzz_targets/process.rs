@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use toy_marketplace::{apply_transactions, read_transactions, InputFormat, PaymentsEngine};
+
+/*
+Reads arbitrary bytes as an input file and applies it, the way the binary does. Errors for
+rows that can't be read or a fatal TransactionError are expected, only a panic is a bug.
+The first byte picks the input format so both readers get fuzzed.
+*/
+fuzz_target!(|data: &[u8]| {
+    let (format, input) = match data.split_first() {
+        Some((first, rest)) if first % 2 == 1 => (InputFormat::Json, rest),
+        Some((_, rest)) => (InputFormat::Csv, rest),
+        None => return,
+    };
+    let mut engine = PaymentsEngine::default();
+    let _ = apply_transactions(
+        read_transactions(input, format),
+        &mut engine,
+        || false,
+        |_, _, _| Ok(()),
+    );
+});
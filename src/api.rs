@@ -0,0 +1,60 @@
+use crate::{Client, Outcome, PaymentsEngine, Transaction};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/*
+The engine as a service, see --serve:
+
+  POST /transactions   a transaction in the json input format, answers with its outcome
+  GET  /clients/{id}   the client's current balances
+
+Every request locks the one engine, so transactions are applied one at a time in the order
+their requests got the lock, the same as rows of a file.
+*/
+pub type SharedEngine = Arc<Mutex<PaymentsEngine>>;
+
+pub fn router(engine: SharedEngine) -> Router {
+    Router::new()
+        .route("/transactions", post(post_transaction))
+        .route("/clients/{id}", get(get_client))
+        .with_state(engine)
+}
+
+// Serves the engine on `addr` until the process is stopped
+pub async fn serve(addr: SocketAddr, engine: SharedEngine) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, router(engine)).await?;
+    Ok(())
+}
+
+async fn post_transaction(
+    State(engine): State<SharedEngine>,
+    Json(transaction): Json<Transaction>,
+) -> Result<Json<Outcome>, (StatusCode, String)> {
+    let mut engine = engine
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // a fatal error only fails this request, the service carries on with the next one
+    engine
+        .apply(transaction)
+        .map(Json)
+        .map_err(|error| (StatusCode::UNPROCESSABLE_ENTITY, error.to_string()))
+}
+
+async fn get_client(
+    State(engine): State<SharedEngine>,
+    Path(id): Path<u16>,
+) -> Result<Json<Client>, StatusCode> {
+    let engine = engine
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    engine
+        .client(id)
+        .cloned()
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
@@ -0,0 +1,302 @@
+use crate::{Outcome, PaymentsEngine, Transaction, TransactionType};
+use anyhow::Result;
+use csv::{Writer, WriterBuilder};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+#[derive(Serialize)]
+struct AuditRow<'a> {
+    // position of the transaction in the input, starting at 1
+    row: usize,
+    #[serde(rename = "type")]
+    transaction_type: &'a TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+    outcome: Outcome,
+    // the client's balances straight after this transaction, empty if the client
+    // doesn't exist (yet)
+    available: Option<Decimal>,
+    held: Option<Decimal>,
+    total: Option<Decimal>,
+    locked: Option<bool>,
+}
+
+/*
+Writes every transaction, in input order, as it was handled: whether it was applied or
+skipped, and a snapshot of the client's balances afterwards. Heavier than the normal
+output, but lets any client's balance be traced back row by row.
+*/
+pub struct AuditLog<W: Write> {
+    writer: Writer<W>,
+    rows: usize,
+}
+
+impl<W: Write> AuditLog<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: WriterBuilder::new().from_writer(writer),
+            rows: 0,
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        transaction: &Transaction,
+        outcome: Outcome,
+        engine: &PaymentsEngine,
+    ) -> Result<()> {
+        self.rows += 1;
+        let client = engine.client(transaction.client_id);
+        self.writer.serialize(AuditRow {
+            row: self.rows,
+            transaction_type: &transaction.transaction_type,
+            client: transaction.client_id,
+            tx: transaction.transaction_id,
+            amount: transaction.amount,
+            outcome,
+            available: client.map(|client| client.available_amount),
+            held: client.map(|client| client.held_amount),
+            total: client.map(|client| client.total_amount),
+            locked: client.map(|client| client.locked),
+        })?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// The formats the ledger can be written in
+#[derive(clap::ValueEnum, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum LedgerFormat {
+    #[default]
+    Csv,
+    // one object with an array of entries per client
+    Json,
+}
+
+// One transaction as it touched a client, see Ledger
+#[derive(Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct LedgerEntry {
+    // position of the transaction in the input, starting at 1, so the entries of different
+    // clients can still be put back in the order they happened
+    pub seq: usize,
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub amount: Option<Decimal>,
+    pub outcome: Outcome,
+    // the client's balances straight after the transaction, empty if the client doesn't
+    // exist (yet)
+    pub available: Option<Decimal>,
+    pub held: Option<Decimal>,
+}
+
+/*
+Every transaction of every client, kept per client in the order they were handled, see
+--ledger. Where AuditLog streams rows out in input order, this is written once the run is
+done, with each client's entries together so their balance can be followed on its own.
+
+An applied transfer is entered for both clients it moved funds between.
+*/
+#[derive(Debug, Default)]
+pub struct Ledger {
+    entries: BTreeMap<u16, Vec<LedgerEntry>>,
+    rows: usize,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, transaction: &Transaction, outcome: Outcome, engine: &PaymentsEngine) {
+        self.rows += 1;
+        let destination = match (&transaction.transaction_type, outcome) {
+            (TransactionType::Transfer, Outcome::Applied) => transaction.destination,
+            _ => None,
+        };
+        for client_id in std::iter::once(transaction.client_id).chain(destination) {
+            let client = engine.client(client_id);
+            self.entries
+                .entry(client_id)
+                .or_default()
+                .push(LedgerEntry {
+                    seq: self.rows,
+                    client: client_id,
+                    tx: transaction.transaction_id,
+                    transaction_type: transaction.transaction_type.clone(),
+                    amount: transaction.amount,
+                    outcome,
+                    available: client.map(|client| client.available_amount),
+                    held: client.map(|client| client.held_amount),
+                });
+        }
+    }
+
+    // The entries of one client, oldest first
+    pub fn entries(&self, client: u16) -> &[LedgerEntry] {
+        self.entries.get(&client).map_or(&[], Vec::as_slice)
+    }
+
+    // As csv rows ordered by client, or as a json object of each client's entries
+    pub fn write<W: Write>(&self, format: LedgerFormat, mut writer: W) -> Result<()> {
+        match format {
+            LedgerFormat::Csv => {
+                let mut writer = WriterBuilder::new().from_writer(writer);
+                for entry in self.entries.values().flatten() {
+                    writer.serialize(entry)?;
+                }
+                writer.flush()?;
+            }
+            LedgerFormat::Json => {
+                serde_json::to_writer(&mut writer, &self.entries)?;
+                writeln!(writer)?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_transactions;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn audit_row_after_a_deposit_shows_the_post_deposit_balance() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,2.5
+resolve,1,1,
+";
+        let mut engine = PaymentsEngine::default();
+        let mut output = Vec::new();
+        let mut audit = AuditLog::new(&mut output);
+        process_transactions(
+            input.as_bytes(),
+            &mut engine,
+            || false,
+            |transaction, outcome, engine| audit.record(transaction, outcome, engine),
+        )
+        .unwrap();
+        audit.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+row,type,client,tx,amount,outcome,available,held,total,locked
+1,deposit,1,1,10.0,applied,10.0,0,10.0,false
+2,deposit,1,2,2.5,applied,12.5,0,12.5,false
+3,resolve,1,1,,skipped,12.5,0,12.5,false
+"
+        );
+    }
+
+    #[test]
+    fn ledger_of_a_deposit_then_dispute() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,4.0
+dispute,1,1,
+dispute,1,3,
+";
+        let mut engine = PaymentsEngine::default();
+        let mut ledger = Ledger::new();
+        process_transactions(
+            input.as_bytes(),
+            &mut engine,
+            || false,
+            |transaction, outcome, engine| {
+                ledger.record(transaction, outcome, engine);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let entry = |seq, tx, transaction_type, amount, outcome, available, held| LedgerEntry {
+            seq,
+            client: 1,
+            tx,
+            transaction_type,
+            amount,
+            outcome,
+            available: Some(available),
+            held: Some(held),
+        };
+        assert_eq!(
+            ledger.entries(1),
+            [
+                entry(
+                    1,
+                    1,
+                    TransactionType::Deposit,
+                    Some(dec!(10.0)),
+                    Outcome::Applied,
+                    dec!(10.0),
+                    dec!(0)
+                ),
+                entry(
+                    3,
+                    1,
+                    TransactionType::Dispute,
+                    None,
+                    Outcome::Applied,
+                    dec!(0),
+                    dec!(10.0)
+                ),
+                entry(
+                    4,
+                    3,
+                    TransactionType::Dispute,
+                    None,
+                    Outcome::Skipped,
+                    dec!(0),
+                    dec!(10.0)
+                ),
+            ]
+        );
+
+        let mut output = Vec::new();
+        ledger.write(LedgerFormat::Csv, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+seq,client,tx,type,amount,outcome,available,held
+1,1,1,deposit,10.0,applied,10.0,0
+3,1,1,dispute,,applied,0.0,10.0
+4,1,3,dispute,,skipped,0.0,10.0
+2,2,2,deposit,4.0,applied,4.0,0
+"
+        );
+    }
+
+    #[test]
+    fn applied_transfer_is_in_both_clients_ledgers() {
+        let mut engine = PaymentsEngine::default();
+        let mut ledger = Ledger::new();
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(5)),
+            Transaction::transfer(1, 2, 2, dec!(2)),
+            Transaction::transfer(1, 3, 2, dec!(9)),
+        ] {
+            let outcome = engine.apply(transaction.clone()).unwrap();
+            ledger.record(&transaction, outcome, &engine);
+        }
+
+        assert_eq!(ledger.entries(1).len(), 3);
+        assert_eq!(ledger.entries(2).len(), 1);
+        assert_eq!(ledger.entries(2)[0].seq, 2);
+        assert_eq!(ledger.entries(2)[0].available, Some(dec!(2)));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{Client, OutputOptions};
+use anyhow::Result;
+use csv::{ReaderBuilder, Trim};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+
+// One way the processed balances differ from the expected output
+#[derive(Debug, PartialEq)]
+pub enum Mismatch {
+    // expected a row for this client, but the run never saw them
+    Missing(Client),
+    // the run produced a client the expected output doesn't have
+    Unexpected(Client),
+    Different { expected: Client, actual: Client },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Missing(expected) => {
+                write!(f, "client {} is expected but missing", expected.id)
+            }
+            Mismatch::Unexpected(actual) => {
+                write!(f, "client {} is not in the expected output", actual.id)
+            }
+            Mismatch::Different { expected, actual } => write!(
+                f,
+                "client {}: expected available={} held={} total={} locked={}, got available={} held={} total={} locked={}",
+                expected.id,
+                expected.available_amount,
+                expected.held_amount,
+                expected.total_amount,
+                expected.locked,
+                actual.available_amount,
+                actual.held_amount,
+                actual.total_amount,
+                actual.locked,
+            ),
+        }
+    }
+}
+
+/*
+Compares the processed clients against an expected output csv.
+
+Like the spec says, row order, spacing and trailing zeros don't matter, so balances are
+compared by value and rows are matched up by client id. Mismatches come back ordered by
+client id.
+
+The clients are compared as `output` would have them written, rounded and without the
+ones it leaves out, so a run always matches the output it wrote itself. Pass the closed
+clients in too, their rows are written as well.
+*/
+pub fn compare<'a, R: Read>(
+    clients: impl IntoIterator<Item = &'a Client>,
+    output: &OutputOptions,
+    expected: R,
+) -> Result<Vec<Mismatch>> {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(expected);
+    let mut expected_clients = BTreeMap::new();
+    for result in rdr.deserialize() {
+        let client: Client = result?;
+        expected_clients.insert(client.id, client);
+    }
+
+    let mut actual_clients: BTreeMap<u16, Client> = clients
+        .into_iter()
+        .filter_map(|client| client.as_written(output))
+        .map(|client| (client.id, client))
+        .collect();
+
+    let mut mismatches = Vec::new();
+    for (id, expected) in expected_clients {
+        match actual_clients.remove(&id) {
+            None => mismatches.push(Mismatch::Missing(expected)),
+            Some(actual) if !same_balances(&expected, &actual) => {
+                mismatches.push(Mismatch::Different { expected, actual })
+            }
+            Some(_) => {}
+        }
+    }
+    for actual in actual_clients.into_values() {
+        mismatches.push(Mismatch::Unexpected(actual));
+    }
+    mismatches.sort_by_key(|mismatch| match mismatch {
+        Mismatch::Missing(client)
+        | Mismatch::Unexpected(client)
+        | Mismatch::Different { actual: client, .. } => client.id,
+    });
+    Ok(mismatches)
+}
+
+fn same_balances(expected: &Client, actual: &Client) -> bool {
+    expected.available_amount == actual.available_amount
+        && expected.held_amount == actual.held_amount
+        && expected.total_amount == actual.total_amount
+        && expected.locked == actual.locked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientList;
+    use rust_decimal_macros::dec;
+
+    fn client_list() -> ClientList {
+        let mut client_list = ClientList::default();
+        let mut first = Client::new(1);
+        first.deposit(dec!(1.5));
+        client_list.insert(1, first);
+        let mut second = Client::new(2);
+        second.deposit(dec!(2));
+        client_list.insert(2, second);
+        client_list
+    }
+
+    #[test]
+    fn matching_expected_output_has_no_mismatches() {
+        let expected = "\
+client, available, held, total, locked
+2, 2.0, 0.0, 2.0, false
+1, 1.5000, 0, 1.5, false
+";
+        assert_eq!(
+            compare(
+                client_list().values(),
+                &OutputOptions::default(),
+                expected.as_bytes()
+            )
+            .unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn mismatching_expected_output_reports_every_difference() {
+        let expected = "\
+client,available,held,total,locked
+1,1.5,0,1.5,true
+3,0,0,0,false
+";
+        let mismatches = compare(
+            client_list().values(),
+            &OutputOptions::default(),
+            expected.as_bytes(),
+        )
+        .unwrap();
+        let ids: Vec<(u16, &str)> = mismatches
+            .iter()
+            .map(|mismatch| match mismatch {
+                Mismatch::Different { actual, .. } => (actual.id, "different"),
+                Mismatch::Unexpected(actual) => (actual.id, "unexpected"),
+                Mismatch::Missing(expected) => (expected.id, "missing"),
+            })
+            .collect();
+        assert_eq!(
+            ids,
+            vec![(1, "different"), (2, "unexpected"), (3, "missing")]
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            "client 1: expected available=1.5 held=0 total=1.5 locked=true, got available=1.5000 held=0.0000 total=1.5000 locked=false"
+        );
+    }
+}
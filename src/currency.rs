@@ -0,0 +1,64 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::prelude::*;
+
+// Minor unit scale (places past the decimal) of the currencies we know about, per ISO 4217
+const SCALES: &[(&str, u32)] = &[
+    ("BHD", 3),
+    ("CHF", 2),
+    ("EUR", 2),
+    ("GBP", 2),
+    ("JPY", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("OMR", 3),
+    ("USD", 2),
+];
+
+// Looks up the scale for a currency code, ignoring case
+pub fn scale(code: &str) -> Result<u32> {
+    let code = code.trim().to_ascii_uppercase();
+    SCALES
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map(|(_, scale)| *scale)
+        .ok_or_else(|| {
+            let known: Vec<&str> = SCALES.iter().map(|(known, _)| *known).collect();
+            anyhow!(
+                "Unknown currency {:?}, expected one of {}",
+                code,
+                known.join(", ")
+            )
+        })
+}
+
+/*
+Rounds an amount to `scale` places with banker's rounding, then pads it so it's always
+written with exactly that many places (10.5 at a scale of 2 is written as 10.50).
+*/
+pub fn rescale(amount: Decimal, scale: u32) -> Decimal {
+    let mut amount = amount.round_dp_with_strategy(scale, RoundingStrategy::MidpointNearestEven);
+    amount.rescale(scale);
+    amount
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn scale_knows_zero_and_three_place_currencies() {
+        assert_eq!(scale("JPY").unwrap(), 0);
+        assert_eq!(scale("usd").unwrap(), 2);
+        assert_eq!(scale("BHD").unwrap(), 3);
+        assert!(scale("XYZ").is_err());
+    }
+
+    #[test]
+    fn rescale_rounds_half_to_even_and_pads() {
+        assert_eq!(rescale(dec!(10.5), 2).to_string(), "10.50");
+        assert_eq!(rescale(dec!(1234.5), 0).to_string(), "1234");
+        assert_eq!(rescale(dec!(1235.5), 0).to_string(), "1236");
+        assert_eq!(rescale(dec!(0.125), 2).to_string(), "0.12");
+    }
+}
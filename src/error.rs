@@ -0,0 +1,216 @@
+use crate::TransactionType;
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+/*
+Why the engine didn't apply a transaction.
+
+Most of these are expected in real input, the spec has the engine ignore the row and carry
+on, and `is_fatal` tells those apart from the ones a run can't continue after.
+*/
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum TransactionError {
+    #[error("transaction {tx} has no amount")]
+    MissingAmount { tx: u32 },
+    #[error("transfer {tx} has no destination client")]
+    MissingDestination { tx: u32 },
+    #[error("transaction {tx} has an amount of zero or less")]
+    NonPositiveAmount { tx: u32 },
+    #[error("transaction {tx} reuses the id of an earlier transaction")]
+    DuplicateTransaction { tx: u32 },
+    #[error("client {client} doesn't have the available funds for transaction {tx}")]
+    InsufficientFunds { client: u16, tx: u32 },
+    #[error("transfer {tx} is to the client sending it")]
+    SelfTransfer { tx: u32 },
+    #[error("client {client} is locked")]
+    AccountLocked { client: u16 },
+    // a client referencing a transaction, or a dispute, that belongs to another client
+    #[error("client {client} can't act on transaction {tx}, it belongs to client {owner}")]
+    ClientMismatch { tx: u32, client: u16, owner: u16 },
+    #[error("transaction {tx} doesn't exist or can't be disputed")]
+    UnknownTransaction { tx: u32 },
+    // e.g. a client closed while one of their disputes was still open
+    #[error("client {client} doesn't exist")]
+    UnknownClient { client: u16 },
+    // only deposits can be disputed, resolved or charged back
+    #[error("transaction {tx} isn't a deposit")]
+    NotADeposit { tx: u32 },
+    // a partial dispute can't hold more than the transaction moved
+    #[error("dispute of transaction {tx} is for more than its amount")]
+    DisputeExceedsAmount { tx: u32 },
+    #[error("transaction {tx} is already disputed")]
+    AlreadyDisputed { tx: u32 },
+    #[error("transaction {tx} isn't disputed")]
+    NotDisputed { tx: u32 },
+    // a balance would go past what Decimal can hold, --saturate caps it instead
+    #[error("transaction {tx} would overflow a balance of client {client}")]
+    Overflow { client: u16, tx: u32 },
+    // a row for a client after their CloseClient
+    #[error("client {client} is closed")]
+    ClientClosed { client: u16 },
+    // see --reserved-clients
+    #[error("client {client} is reserved")]
+    ReservedClient { client: u16 },
+    // see --max-clients and --max-transactions, these stop the run
+    #[error("transaction {tx} would add a client past the limit of {max}")]
+    TooManyClients { tx: u32, max: usize },
+    #[error("transaction {tx} would keep a transaction past the limit of {max}")]
+    TooManyTransactions { tx: u32, max: usize },
+    // left out by --only-types
+    #[error("transaction {tx} isn't one of the types being processed")]
+    Excluded { tx: u32 },
+    #[error("Invariant violated for client {client}: available {available} + held {held} + pending {pending} != total {total}, after {transaction_type:?} transaction {tx}")]
+    InvariantViolated {
+        client: u16,
+        tx: u32,
+        transaction_type: TransactionType,
+        available: Decimal,
+        held: Decimal,
+        pending: Decimal,
+        total: Decimal,
+    },
+    // the engine's store (see --db) couldn't be written to
+    #[error("couldn't save to the store: {message}")]
+    Store { message: String },
+}
+
+/*
+Why an input couldn't be read at all, as opposed to one of its rows. Unlike a row that
+can't be read, these stop the run even without --strict.
+*/
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum InputError {
+    #[error("missing required column: {column}")]
+    MissingColumn { column: &'static str },
+}
+
+impl TransactionError {
+    // A short name for the kind of error, for counting them
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TransactionError::MissingAmount { .. } => "missing amount",
+            TransactionError::MissingDestination { .. } => "missing destination",
+            TransactionError::NonPositiveAmount { .. } => "amount not positive",
+            TransactionError::DuplicateTransaction { .. } => "duplicate transaction",
+            TransactionError::InsufficientFunds { .. } => "insufficient funds",
+            TransactionError::SelfTransfer { .. } => "transfer to self",
+            TransactionError::AccountLocked { .. } => "account locked",
+            TransactionError::ClientMismatch { .. } => "client mismatch",
+            TransactionError::UnknownTransaction { .. } => "unknown transaction",
+            TransactionError::UnknownClient { .. } => "unknown client",
+            TransactionError::NotADeposit { .. } => "not a deposit",
+            TransactionError::DisputeExceedsAmount { .. } => "dispute exceeds amount",
+            TransactionError::AlreadyDisputed { .. } => "already disputed",
+            TransactionError::NotDisputed { .. } => "not disputed",
+            TransactionError::Overflow { .. } => "overflow",
+            TransactionError::ClientClosed { .. } => "client closed",
+            TransactionError::ReservedClient { .. } => "reserved client",
+            TransactionError::TooManyClients { .. } => "too many clients",
+            TransactionError::TooManyTransactions { .. } => "too many transactions",
+            TransactionError::Excluded { .. } => "excluded by --only-types",
+            TransactionError::InvariantViolated { .. } => "invariant violated",
+            TransactionError::Store { .. } => "store failed",
+        }
+    }
+
+    // Fatal errors stop the run, the rest only mean the row was skipped. A row missing its
+    // amount or destination is only skipped too, one bad row shouldn't cost the whole run
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            TransactionError::TooManyClients { .. }
+                | TransactionError::TooManyTransactions { .. }
+                | TransactionError::InvariantViolated { .. }
+                | TransactionError::Store { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorMode, Outcome, PaymentsEngine, Transaction};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn each_rejected_transaction_says_why() {
+        let mut engine = PaymentsEngine::default();
+        engine.process(Transaction::deposit(1, 1, dec!(5))).unwrap();
+        engine.process(Transaction::deposit(2, 2, dec!(5))).unwrap();
+
+        let cases = [
+            (
+                Transaction::new(TransactionType::Deposit, 1, 3, None),
+                TransactionError::MissingAmount { tx: 3 },
+            ),
+            (
+                Transaction::deposit(1, 4, dec!(-1)),
+                TransactionError::NonPositiveAmount { tx: 4 },
+            ),
+            (
+                Transaction::withdrawal(1, 5, dec!(6)),
+                TransactionError::InsufficientFunds { client: 1, tx: 5 },
+            ),
+            (
+                Transaction::dispute(2, 1),
+                TransactionError::ClientMismatch {
+                    tx: 1,
+                    client: 2,
+                    owner: 1,
+                },
+            ),
+            (
+                Transaction::dispute(1, 99),
+                TransactionError::UnknownTransaction { tx: 99 },
+            ),
+            (
+                Transaction::resolve(1, 1),
+                TransactionError::NotDisputed { tx: 1 },
+            ),
+        ];
+        for (transaction, error) in cases {
+            assert_eq!(engine.apply_all([transaction], ErrorMode::Stop), Err(error));
+        }
+
+        engine.process(Transaction::dispute(1, 1)).unwrap();
+        assert_eq!(
+            engine.apply_all([Transaction::dispute(1, 1)], ErrorMode::Stop),
+            Err(TransactionError::AlreadyDisputed { tx: 1 })
+        );
+        engine.process(Transaction::chargeback(1, 1)).unwrap();
+        assert_eq!(
+            engine.apply_all([Transaction::deposit(1, 6, dec!(1))], ErrorMode::Stop),
+            Err(TransactionError::AccountLocked { client: 1 })
+        );
+    }
+
+    #[test]
+    fn only_fatal_errors_stop_apply() {
+        let mut engine = PaymentsEngine::new(crate::Options {
+            max_clients: Some(1),
+            ..crate::Options::default()
+        });
+        assert_eq!(
+            engine.apply(Transaction::withdrawal(1, 1, dec!(1))),
+            Ok(Outcome::Skipped)
+        );
+        assert_eq!(
+            engine.apply(Transaction::new(TransactionType::Deposit, 1, 2, None)),
+            Ok(Outcome::Skipped)
+        );
+        assert_eq!(
+            engine.apply(Transaction::new(
+                TransactionType::Transfer,
+                1,
+                3,
+                Some(dec!(1))
+            )),
+            Ok(Outcome::Skipped)
+        );
+        assert_eq!(
+            engine.apply(Transaction::deposit(2, 4, dec!(1))),
+            Err(TransactionError::TooManyClients { tx: 4, max: 1 })
+        );
+        assert_eq!(engine.stats().ignored.get("missing amount"), Some(&1));
+    }
+}
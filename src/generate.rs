@@ -0,0 +1,207 @@
+use crate::{Transaction, TransactionType};
+use anyhow::Result;
+use csv::WriterBuilder;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+
+// largest amount a generated deposit can carry, in ten-thousandths
+const MAX_AMOUNT: i64 = 10_000_000;
+
+/*
+The Transaction struct can't be written by the csv serializer directly since its
+amount goes through serde_json's arbitrary precision Number, so each generated
+transaction is flattened into one of these rows before being written.
+*/
+#[derive(Serialize)]
+struct Row {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+}
+
+impl From<&Transaction> for Row {
+    fn from(transaction: &Transaction) -> Self {
+        Self {
+            transaction_type: transaction.transaction_type.clone(),
+            client: transaction.client_id,
+            tx: transaction.transaction_id,
+            amount: transaction.amount,
+        }
+    }
+}
+
+// What the generator remembers about a client so every row it emits is valid
+#[derive(Default)]
+struct ClientState {
+    available: Decimal,
+    // deposits that can still be disputed
+    deposits: Vec<(u32, Decimal)>,
+    // deposits that are currently disputed, waiting for a resolve or chargeback
+    disputed: Vec<(u32, Decimal)>,
+}
+
+/*
+Builds `transaction_count` random transactions spread over clients 1 to `client_count`.
+
+The same seed always produces the same list. Every row is consistent with the ones
+before it: withdrawals never exceed what the client has available, disputes only
+reference earlier deposits of the same client, and resolves/chargebacks only reference
+transactions that are currently disputed.
+*/
+pub fn generate_transactions(
+    client_count: u16,
+    transaction_count: usize,
+    seed: u64,
+) -> Vec<Transaction> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut clients: HashMap<u16, ClientState> = HashMap::new();
+    let mut transactions = Vec::with_capacity(transaction_count);
+    let mut next_transaction_id: u32 = 1;
+
+    while transactions.len() < transaction_count {
+        let client_id = rng.gen_range(1..=client_count.max(1));
+        let client = clients.entry(client_id).or_default();
+
+        let transaction = match rng.gen_range(0..100) {
+            0..=19 if client.available > Decimal::ZERO => {
+                let most = (client.available * dec!(10000)).to_i64().unwrap_or(1);
+                let amount = Decimal::new(rng.gen_range(1..=most), 4);
+                client.available -= amount;
+                Transaction::withdrawal(client_id, take_id(&mut next_transaction_id), amount)
+            }
+            20..=29 if !client.deposits.is_empty() => {
+                let (id, amount) = client
+                    .deposits
+                    .swap_remove(rng.gen_range(0..client.deposits.len()));
+                client.available -= amount;
+                client.disputed.push((id, amount));
+                Transaction::dispute(client_id, id)
+            }
+            30..=34 if !client.disputed.is_empty() => {
+                let (id, amount) = client
+                    .disputed
+                    .swap_remove(rng.gen_range(0..client.disputed.len()));
+                client.available += amount;
+                client.deposits.push((id, amount));
+                Transaction::resolve(client_id, id)
+            }
+            35..=36 if !client.disputed.is_empty() => {
+                let (id, _) = client
+                    .disputed
+                    .swap_remove(rng.gen_range(0..client.disputed.len()));
+                Transaction::chargeback(client_id, id)
+            }
+            _ => {
+                let amount = Decimal::new(rng.gen_range(1..=MAX_AMOUNT), 4);
+                let id = take_id(&mut next_transaction_id);
+                client.available += amount;
+                client.deposits.push((id, amount));
+                Transaction::deposit(client_id, id, amount)
+            }
+        };
+        transactions.push(transaction);
+    }
+    transactions
+}
+
+/*
+`n` random transactions from `seed`, for tests that need a realistic run without writing one
+out by hand. The same as generate_transactions with about one client per 20 transactions,
+at most 1000.
+
+A client is picked uniformly for each row, then what they get is:
+
+  deposit     63%, and whenever the row drawn can't be made for the client
+  withdrawal  20%, of at most what they have available
+  dispute     10%, of one of their deposits that isn't disputed
+  resolve      5%, of one of their disputed deposits
+  chargeback   2%, of one of their disputed deposits
+
+Deposits are between 0.0001 and 1000 with four decimal places. A charged back client is
+locked, so the engine ignores the rows generated for them after it.
+*/
+#[cfg(feature = "testing")]
+pub fn gen_scenario(seed: u64, n: usize) -> Vec<Transaction> {
+    let client_count = (n / 20).clamp(1, 1000) as u16;
+    generate_transactions(client_count, n, seed)
+}
+
+// writes the generated transactions as a csv with the same header the engine reads
+pub fn generate<W: Write>(
+    client_count: u16,
+    transaction_count: usize,
+    seed: u64,
+    writer: W,
+) -> Result<()> {
+    let mut writer = WriterBuilder::new().from_writer(writer);
+    for transaction in generate_transactions(client_count, transaction_count, seed) {
+        writer.serialize(Row::from(&transaction))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// hands out transaction ids in order, starting from 1
+fn take_id(next_transaction_id: &mut u32) -> u32 {
+    let transaction_id = *next_transaction_id;
+    *next_transaction_id += 1;
+    transaction_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generating_with_the_same_seed_twice_produces_identical_output() {
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        generate(5, 500, 42, &mut first).unwrap();
+        generate(5, 500, 42, &mut second).unwrap();
+        assert_eq!(first, second);
+
+        let mut other_seed = Vec::new();
+        generate(5, 500, 43, &mut other_seed).unwrap();
+        assert_ne!(first, other_seed);
+    }
+
+    #[test]
+    fn generated_output_can_be_read_back_as_transactions() {
+        let mut output = Vec::new();
+        generate(3, 200, 7, &mut output).unwrap();
+
+        let mut rdr = csv::Reader::from_reader(output.as_slice());
+        let transactions: Vec<Transaction> = rdr
+            .deserialize()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(transactions, generate_transactions(3, 200, 7));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn generated_scenario_applies_without_error() {
+        use crate::{Outcome, PaymentsEngine};
+        let mut engine = PaymentsEngine::default();
+        let mut locked = std::collections::HashSet::new();
+        for transaction in gen_scenario(3, 5_000) {
+            let client = transaction.client_id;
+            let charged_back = transaction.transaction_type == TransactionType::Chargeback;
+            let outcome = engine.apply(transaction).unwrap();
+            if !locked.contains(&client) {
+                assert_eq!(outcome, Outcome::Applied);
+            }
+            if charged_back {
+                locked.insert(client);
+            }
+        }
+        assert!(engine.clients().all(|client| client.is_consistent()));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{Outcome, Transaction, TransactionType};
+use anyhow::Result;
+use csv::WriterBuilder;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+// The optional input columns that deposits and withdrawals can be grouped by
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Region,
+    Segment,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Clone)]
+pub struct GroupTotal {
+    // rows without a value for the column are grouped under an empty name
+    group: String,
+    deposits: Decimal,
+    withdrawals: Decimal,
+    // deposits - withdrawals
+    net: Decimal,
+}
+
+/*
+Sums the deposits and withdrawals that were actually applied, per value of the
+--group-by column. Ordered by group so the output is stable between runs.
+*/
+pub struct GroupTotals {
+    by: GroupBy,
+    totals: BTreeMap<String, GroupTotal>,
+}
+
+impl GroupTotals {
+    pub fn new(by: GroupBy) -> Self {
+        Self {
+            by,
+            totals: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, transaction: &Transaction, outcome: Outcome) {
+        if outcome != Outcome::Applied {
+            return;
+        }
+        let amount = match (&transaction.transaction_type, transaction.amount) {
+            (TransactionType::Deposit | TransactionType::Withdrawal, Some(amount)) => amount,
+            _ => return,
+        };
+
+        let group = match self.by {
+            GroupBy::Region => &transaction.region,
+            GroupBy::Segment => &transaction.segment,
+        }
+        .clone()
+        .unwrap_or_default();
+
+        let total = self
+            .totals
+            .entry(group.clone())
+            .or_insert_with(|| GroupTotal {
+                group,
+                ..GroupTotal::default()
+            });
+        if transaction.transaction_type == TransactionType::Deposit {
+            total.deposits += amount;
+            total.net += amount;
+        } else {
+            total.withdrawals += amount;
+            total.net -= amount;
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = WriterBuilder::new().from_writer(writer);
+        for total in self.totals.values() {
+            writer.serialize(total)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_transactions, PaymentsEngine};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn two_regions_aggregate_separately() {
+        let input = "\
+type,client,tx,amount,region
+deposit,1,1,10.0,eu
+deposit,2,2,7.5,us
+withdrawl,1,3,2.5,eu
+deposit,1,4,1.0,eu
+withdrawl,2,5,0.5,us
+dispute,1,1,,eu
+";
+        let mut engine = PaymentsEngine::default();
+        let mut groups = GroupTotals::new(GroupBy::Region);
+        process_transactions(
+            input.as_bytes(),
+            &mut engine,
+            || false,
+            |transaction, outcome, _| {
+                groups.record(transaction, outcome);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let totals: Vec<&GroupTotal> = groups.totals.values().collect();
+        assert_eq!(
+            totals,
+            vec![
+                &GroupTotal {
+                    group: "eu".to_string(),
+                    deposits: dec!(11),
+                    withdrawals: dec!(2.5),
+                    net: dec!(8.5),
+                },
+                &GroupTotal {
+                    group: "us".to_string(),
+                    deposits: dec!(7.5),
+                    withdrawals: dec!(0.5),
+                    net: dec!(7),
+                },
+            ]
+        );
+
+        let mut output = Vec::new();
+        groups.write(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "group,deposits,withdrawals,net\neu,11.0,2.5,8.5\nus,7.5,0.5,7.0\n"
+        );
+    }
+}
@@ -0,0 +1,4422 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use csv::{ReaderBuilder, Trim, WriterBuilder};
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use tracing::{debug, warn};
+
+pub use error::{InputError, TransactionError};
+pub use snapshot::EngineState;
+use stats::Stats;
+use store::{MemoryStore, Store, TransactionStore};
+
+#[cfg(feature = "api")]
+pub mod api;
+pub mod audit;
+pub mod compare;
+pub mod currency;
+pub mod error;
+pub mod generate;
+pub mod group;
+// threads can't be spawned in the browser
+#[cfg(not(target_arch = "wasm32"))]
+pub mod parallel;
+pub mod resume;
+pub mod seed;
+pub mod snapshot;
+pub mod stats;
+pub mod store;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod table;
+pub mod totals;
+pub mod wasm;
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    // a charge from the marketplace, applied like a withdrawal but reported separately
+    Fee,
+    Dispute,
+    Resolve,
+    Chargeback,
+    // moves a client's pending deposits into available, only meaningful with --pending
+    Settle,
+    // no more transactions will reference the client, so they're written out straight
+    // away and dropped from memory
+    CloseClient,
+    // moves available funds from the client to the transaction's destination client
+    Transfer,
+}
+
+// the names types are written with, what serialize uses too
+const TRANSACTION_TYPE_NAMES: &[&str] = &[
+    "deposit",
+    "withdrawal",
+    "fee",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "settle",
+    "closeclient",
+    "transfer",
+];
+
+/*
+Exported csvs don't always stick to the lowercase names, so surrounding whitespace and case
+are ignored: " Deposit" and "CHARGEBACK" are read like "deposit" and "chargeback".
+*/
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = std::borrow::Cow::<str>::deserialize(deserializer)?;
+        match value.trim().to_lowercase().as_str() {
+            "deposit" => Ok(TransactionType::Deposit),
+            // the misspelling older input files use
+            "withdrawal" | "withdrawl" => Ok(TransactionType::Withdrawal),
+            "fee" => Ok(TransactionType::Fee),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            "settle" => Ok(TransactionType::Settle),
+            "closeclient" => Ok(TransactionType::CloseClient),
+            "transfer" => Ok(TransactionType::Transfer),
+            _ => Err(serde::de::Error::unknown_variant(
+                &value,
+                TRANSACTION_TYPE_NAMES,
+            )),
+        }
+    }
+}
+
+// The transactions that move money into or out of an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandardType {
+    Deposit,
+    Withdrawal,
+    Fee,
+}
+
+// The transactions that act on an earlier deposit, referenced by its id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaType {
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+/*
+What kind of transaction a type read off the wire is. handle_transaction dispatches on this,
+so the standard and meta handlers only ever get the types they handle.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Standard(StandardType),
+    Meta(MetaType),
+    Settle,
+    CloseClient,
+    Transfer,
+}
+
+impl From<&TransactionType> for TransactionKind {
+    fn from(transaction_type: &TransactionType) -> Self {
+        match transaction_type {
+            TransactionType::Deposit => TransactionKind::Standard(StandardType::Deposit),
+            TransactionType::Withdrawal => TransactionKind::Standard(StandardType::Withdrawal),
+            TransactionType::Fee => TransactionKind::Standard(StandardType::Fee),
+            TransactionType::Dispute => TransactionKind::Meta(MetaType::Dispute),
+            TransactionType::Resolve => TransactionKind::Meta(MetaType::Resolve),
+            TransactionType::Chargeback => TransactionKind::Meta(MetaType::Chargeback),
+            TransactionType::Settle => TransactionKind::Settle,
+            TransactionType::CloseClient => TransactionKind::CloseClient,
+            TransactionType::Transfer => TransactionKind::Transfer,
+        }
+    }
+}
+
+impl From<StandardType> for TransactionType {
+    fn from(standard_type: StandardType) -> Self {
+        match standard_type {
+            StandardType::Deposit => TransactionType::Deposit,
+            StandardType::Withdrawal => TransactionType::Withdrawal,
+            StandardType::Fee => TransactionType::Fee,
+        }
+    }
+}
+
+impl From<MetaType> for TransactionType {
+    fn from(meta_type: MetaType) -> Self {
+        match meta_type {
+            MetaType::Dispute => TransactionType::Dispute,
+            MetaType::Resolve => TransactionType::Resolve,
+            MetaType::Chargeback => TransactionType::Chargeback,
+        }
+    }
+}
+
+// Parses a type as it's spelled in the csv, so flags and input files use the same names
+pub fn parse_transaction_type(value: &str) -> Result<TransactionType> {
+    use serde::de::{value::StrDeserializer, IntoDeserializer};
+    let deserializer: StrDeserializer<serde::de::value::Error> = value.into_deserializer();
+    Ok(TransactionType::deserialize(deserializer)?)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "lowercase")]
+pub struct Client {
+    #[serde(rename = "client")]
+    pub(crate) id: u16,
+
+    #[serde(rename = "available")]
+    pub(crate) available_amount: Decimal,
+
+    #[serde(rename = "held")]
+    pub(crate) held_amount: Decimal,
+
+    // deposited but not yet settled, only tracked (and written out) with --pending
+    #[serde(rename = "pending", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pending_amount: Option<Decimal>,
+
+    #[serde(rename = "total")]
+    pub(crate) total_amount: Decimal,
+
+    pub(crate) locked: bool,
+
+    // set by --fraud-heuristics, reported on stderr rather than written out
+    #[serde(skip)]
+    pub(crate) suspected_fraud: bool,
+}
+
+impl Client {
+    // Whether available, held and pending funds still add up to the total
+    pub fn is_consistent(&self) -> bool {
+        self.total_recomputed() == self.total_amount
+    }
+
+    // The total worked out from the other balances, instead of the one kept up to date
+    // alongside them
+    pub fn total_recomputed(&self) -> Decimal {
+        self.available_amount + self.held_amount + self.pending_amount.unwrap_or_default()
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Funds the client can withdraw or trade with right now.
+    ///
+    /// ```
+    /// use rust_decimal_macros::dec;
+    /// use toy_marketplace::{PaymentsEngine, Transaction};
+    ///
+    /// let mut engine = PaymentsEngine::default();
+    /// engine.process(Transaction::deposit(1, 1, dec!(10.5))).unwrap();
+    /// let client = engine.client(1).unwrap();
+    /// assert_eq!(client.id(), 1);
+    /// assert_eq!(client.available(), dec!(10.5));
+    /// assert_eq!(client.held(), dec!(0));
+    /// assert_eq!(client.total(), dec!(10.5));
+    /// assert!(!client.is_locked());
+    /// ```
+    pub fn available(&self) -> Decimal {
+        self.available_amount
+    }
+
+    // funds under dispute
+    pub fn held(&self) -> Decimal {
+        self.held_amount
+    }
+
+    // deposited but not yet settled, None unless the engine runs with --pending
+    pub fn pending(&self) -> Option<Decimal> {
+        self.pending_amount
+    }
+
+    pub fn total(&self) -> Decimal {
+        self.total_amount
+    }
+
+    // locked by a chargeback, nothing but closing the account touches it anymore
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    // flagged by --fraud-heuristics
+    pub fn is_suspected_fraud(&self) -> bool {
+        self.suspected_fraud
+    }
+
+    // the client with the recomputed total, warning if the kept one had drifted from it
+    fn with_recomputed_total(&self) -> Self {
+        let total = self.total_recomputed();
+        if total != self.total_amount {
+            warn!(
+                client = self.id,
+                total = %self.total_amount,
+                recomputed = %total,
+                "total doesn't match the balances, writing the recomputed one"
+            );
+        }
+        Self {
+            total_amount: total,
+            ..self.clone()
+        }
+    }
+
+    // Builds a client from just the balances that matter, see ClientBuilder
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    fn new(id: u16) -> Self {
+        Self {
+            id,
+            available_amount: dec!(0),
+            held_amount: dec!(0),
+            pending_amount: None,
+            total_amount: dec!(0),
+            locked: false,
+            suspected_fraud: false,
+        }
+    }
+
+    // a client whose deposits land in pending until they're settled
+    fn with_pending(id: u16) -> Self {
+        Self {
+            pending_amount: Some(dec!(0)),
+            ..Self::new(id)
+        }
+    }
+
+    /*
+    The mutators below answer None, and leave the client as it was, if a balance would
+    overflow Decimal. The handlers skip the row then, see TransactionError::Overflow, and
+    --saturate has the saturating versions further down instead.
+    */
+
+    // increases available and total funds by amount
+    fn deposit(&mut self, amount: Decimal) -> Option<()> {
+        let available = self.available_amount.checked_add(amount)?;
+        let total = self.total_amount.checked_add(amount)?;
+        self.available_amount = available;
+        self.total_amount = total;
+        Some(())
+    }
+
+    // decreases available and total funds by amount
+    fn withdraw(&mut self, amount: Decimal) -> Option<()> {
+        let available = self.available_amount.checked_sub(amount)?;
+        let total = self.total_amount.checked_sub(amount)?;
+        self.available_amount = available;
+        self.total_amount = total;
+        Some(())
+    }
+
+    // available funds should decrease by amount,
+    //    held should increase by amount.
+    // total should remain the same
+    fn hold(&mut self, amount: Decimal) -> Option<()> {
+        let available = self.available_amount.checked_sub(amount)?;
+        let held = self.held_amount.checked_add(amount)?;
+        self.available_amount = available;
+        self.held_amount = held;
+        Some(())
+    }
+
+    // held funds should decrease by the amount
+    // available funds should increase by the maount
+    // total should remain the same
+    fn release(&mut self, amount: Decimal) -> Option<()> {
+        let held = self.held_amount.checked_sub(amount)?;
+        let available = self.available_amount.checked_add(amount)?;
+        self.held_amount = held;
+        self.available_amount = available;
+        Some(())
+    }
+
+    // held funds should decrease by the amount
+    // total funds should decrease by the amount
+    // available should remain the same
+    fn chargeback(&mut self, amount: Decimal) -> Option<()> {
+        let held = self.held_amount.checked_sub(amount)?;
+        let total = self.total_amount.checked_sub(amount)?;
+        self.held_amount = held;
+        self.total_amount = total;
+        Some(())
+    }
+
+    fn freeze(&mut self) {
+        self.locked = true;
+    }
+
+    // increases pending and total funds by amount, available is untouched until a settle
+    fn deposit_pending(&mut self, amount: Decimal) -> Option<()> {
+        let pending = self
+            .pending_amount
+            .unwrap_or_default()
+            .checked_add(amount)?;
+        let total = self.total_amount.checked_add(amount)?;
+        self.pending_amount = Some(pending);
+        self.total_amount = total;
+        Some(())
+    }
+
+    // moves everything pending into available
+    // total should remain the same
+    fn settle(&mut self) -> Option<()> {
+        if let Some(pending) = self.pending_amount {
+            self.available_amount = self.available_amount.checked_add(pending)?;
+            self.pending_amount = Some(dec!(0));
+        }
+        Some(())
+    }
+
+    // the client as it should be written out, with every balance rounded and padded to the
+    // output's `scale` places if given and `precision` (or PRECISION) places otherwise, so
+    // every balance in a row has the same number of places like Display and the table
+    fn normalized(&self, output: &OutputOptions) -> Self {
+        let places = output
+            .scale
+            .unwrap_or(output.precision.unwrap_or(PRECISION));
+        // rounded only here, so the arithmetic before keeps every place it was given.
+        // Half to even, so rounding a lot of balances doesn't drift them all upwards
+        let normalize = |amount: Decimal| normalize_zero(currency::rescale(amount, places), places);
+        let available_amount = normalize(self.available_amount);
+        let held_amount = normalize(self.held_amount);
+        let pending_amount = self.pending_amount.map(normalize);
+        // added up from the rounded balances, like round_balances does, so the row written
+        // adds up too. A total that had drifted is only rounded, it stays visible unless
+        // --recompute-totals
+        let total_amount = available_amount
+            .checked_add(held_amount)
+            .and_then(|total| total.checked_add(pending_amount.unwrap_or_default()))
+            .filter(|_| self.is_consistent())
+            .map_or_else(|| normalize(self.total_amount), normalize);
+        Self {
+            available_amount,
+            held_amount,
+            pending_amount,
+            total_amount,
+            ..self.clone()
+        }
+    }
+
+    // the client as write_clients writes them, None if `output` leaves them out
+    pub(crate) fn as_written(&self, output: &OutputOptions) -> Option<Self> {
+        if output.with_holds && self.held_amount.is_zero() || output.skip_clients.contains(&self.id)
+        {
+            return None;
+        }
+        let client = if output.recompute_totals {
+            Self {
+                total_amount: self.total_recomputed(),
+                ..self.clone()
+            }
+        } else {
+            self.clone()
+        };
+        Some(client.normalized(output))
+    }
+
+    /*
+    Saturating versions of the mutators above, used with --saturate.
+    Where the full amount would take a balance past what Decimal holds, only as much as
+    every balance it touches can take is moved. Each balance moves by the same amount, so
+    they still add up, and dirty data can be processed to the end with bounded (if not
+    exact) balances.
+    */
+    fn saturating_deposit(&mut self, amount: Decimal) {
+        let amount = saturated(
+            self.id,
+            amount,
+            &[self.available_amount, self.total_amount],
+            &[],
+        );
+        self.available_amount += amount;
+        self.total_amount += amount;
+    }
+
+    fn saturating_withdraw(&mut self, amount: Decimal) {
+        let amount = saturated(
+            self.id,
+            amount,
+            &[],
+            &[self.available_amount, self.total_amount],
+        );
+        self.available_amount -= amount;
+        self.total_amount -= amount;
+    }
+
+    fn saturating_hold(&mut self, amount: Decimal) {
+        let amount = saturated(
+            self.id,
+            amount,
+            &[self.held_amount],
+            &[self.available_amount],
+        );
+        self.available_amount -= amount;
+        self.held_amount += amount;
+    }
+
+    fn saturating_release(&mut self, amount: Decimal) {
+        let amount = saturated(
+            self.id,
+            amount,
+            &[self.available_amount],
+            &[self.held_amount],
+        );
+        self.held_amount -= amount;
+        self.available_amount += amount;
+    }
+
+    fn saturating_chargeback(&mut self, amount: Decimal) {
+        let amount = saturated(self.id, amount, &[], &[self.held_amount, self.total_amount]);
+        self.held_amount -= amount;
+        self.total_amount -= amount;
+    }
+}
+
+/*
+Sets up a client balance by balance, for tests and callers that need one in a given state.
+Anything not set is zero, unlocked and without a pending balance. The total isn't worked
+out from the other balances, set it too.
+*/
+#[derive(Debug, Default, Clone)]
+pub struct ClientBuilder {
+    id: u16,
+    available: Decimal,
+    held: Decimal,
+    pending: Option<Decimal>,
+    total: Decimal,
+    locked: bool,
+}
+
+impl ClientBuilder {
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn available(mut self, amount: Decimal) -> Self {
+        self.available = amount;
+        self
+    }
+
+    pub fn held(mut self, amount: Decimal) -> Self {
+        self.held = amount;
+        self
+    }
+
+    pub fn pending(mut self, amount: Decimal) -> Self {
+        self.pending = Some(amount);
+        self
+    }
+
+    pub fn total(mut self, amount: Decimal) -> Self {
+        self.total = amount;
+        self
+    }
+
+    pub fn locked(mut self, locked: bool) -> Self {
+        self.locked = locked;
+        self
+    }
+
+    pub fn build(self) -> Client {
+        Client {
+            id: self.id,
+            available_amount: self.available,
+            held_amount: self.held,
+            pending_amount: self.pending,
+            total_amount: self.total,
+            locked: self.locked,
+            suspected_fraud: false,
+        }
+    }
+}
+
+// Balances at PRECISION places, e.g. `client 1: available=10.0000 held=5.0000 total=15.0000 locked=false`
+impl std::fmt::Display for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let amount =
+            |amount: Decimal| normalize_zero(currency::rescale(amount, PRECISION), PRECISION);
+        write!(
+            f,
+            "client {}: available={} held={} total={} locked={}",
+            self.id,
+            amount(self.available_amount),
+            amount(self.held_amount),
+            amount(self.total_amount),
+            self.locked
+        )?;
+        if let Some(pending) = self.pending_amount {
+            write!(f, " pending={}", amount(pending))?;
+        }
+        Ok(())
+    }
+}
+
+// Places past the decimal that balances are written with, per the spec
+const PRECISION: u32 = 4;
+
+// A zero balance is always written as 0.0000 (at the given scale), never -0.0000 or 0.0
+fn normalize_zero(amount: Decimal, scale: u32) -> Decimal {
+    if amount.is_zero() {
+        Decimal::new(0, scale)
+    } else {
+        amount
+    }
+}
+
+// The most of `amount` that can be added to every balance in `raised` and taken from every
+// one in `lowered` without overflowing, all of it unless one would
+fn saturated(client_id: u16, amount: Decimal, raised: &[Decimal], lowered: &[Decimal]) -> Decimal {
+    // no room is missing when there's more than Decimal::MAX of it
+    let room = raised
+        .iter()
+        .filter_map(|balance| Decimal::MAX.checked_sub(*balance))
+        .chain(
+            lowered
+                .iter()
+                .filter_map(|balance| balance.checked_sub(Decimal::MIN)),
+        )
+        .fold(amount, Decimal::min);
+    if room < amount {
+        warn!(
+            client = client_id,
+            "balance saturated at what a Decimal holds"
+        );
+    }
+    room
+}
+
+// Settings from the command line that change how the balances are written
+#[derive(Debug, Default, Clone)]
+pub struct OutputOptions {
+    // round and pad to this many decimal places, for a currency's minor unit
+    pub scale: Option<u32>,
+    // round and pad to this many decimal places instead of PRECISION
+    pub precision: Option<u32>,
+    // when set, only these columns are written after the client id
+    pub columns: Option<Vec<Column>>,
+    // only write clients with funds held by a dispute
+    pub with_holds: bool,
+    // clients an earlier, interrupted run already wrote out
+    pub skip_clients: HashSet<u16>,
+    // leave out the header row, when appending to output that already has one
+    pub skip_header: bool,
+    pub format: OutputFormat,
+    pub sort: SortOrder,
+    // what separates the csv fields, a comma when not set
+    pub delimiter: Option<u8>,
+    // separate thousands with commas, only in the table
+    pub group_digits: bool,
+    // write each total as available + held (+ pending) instead of the one kept as
+    // transactions were applied
+    pub recompute_totals: bool,
+}
+
+// The order clients are written in
+#[derive(clap::ValueEnum, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum SortOrder {
+    // ascending client id, so the same input always gives the same output
+    #[default]
+    Id,
+    // whatever order the clients are stored in, which has nothing to do with their ids
+    None,
+}
+
+// The formats balances can be written in
+#[derive(clap::ValueEnum, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    // a single array of client objects, always with every column
+    Json,
+    // columns lined up for reading in a terminal, not meant to be parsed
+    Table,
+}
+
+// Whether balances are rounded as each transaction is applied, and to which neighbour a
+// midpoint goes. Rounding as it's applied matches ledgers that only ever keep rounded
+// balances: three deposits of 0.00005 are 0.0003 rounding half up, and nothing half to even
+#[derive(clap::ValueEnum, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum RoundEach {
+    // keep every place, balances are only rounded when they're written
+    #[default]
+    None,
+    // 0.00005 to 0.0001
+    HalfUp,
+    // 0.00005 to 0.0000 and 0.00015 to 0.0002
+    HalfEven,
+}
+
+impl RoundEach {
+    fn strategy(self) -> Option<RoundingStrategy> {
+        match self {
+            RoundEach::None => None,
+            RoundEach::HalfUp => Some(RoundingStrategy::MidpointAwayFromZero),
+            RoundEach::HalfEven => Some(RoundingStrategy::MidpointNearestEven),
+        }
+    }
+}
+
+// The client columns that can be picked with --columns, the client id is always written
+#[derive(clap::ValueEnum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Column {
+    Available,
+    Held,
+    Pending,
+    Total,
+    Locked,
+}
+
+impl Column {
+    // the header, same as the one Client is serialized with
+    fn name(self) -> &'static str {
+        match self {
+            Column::Available => "available",
+            Column::Held => "held",
+            Column::Pending => "pending",
+            Column::Total => "total",
+            Column::Locked => "locked",
+        }
+    }
+
+    fn value(self, client: &Client) -> String {
+        match self {
+            Column::Available => client.available_amount.to_string(),
+            Column::Held => client.held_amount.to_string(),
+            // empty when the run isn't tracking pending deposits
+            Column::Pending => client
+                .pending_amount
+                .map(|amount| amount.to_string())
+                .unwrap_or_default(),
+            Column::Total => client.total_amount.to_string(),
+            Column::Locked => client.locked.to_string(),
+        }
+    }
+}
+
+// Settings from the command line that change how transactions are applied
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    pub saturate: bool,
+    // how far below zero a withdrawal can take available funds, zero by default
+    pub overdraft: Decimal,
+    // deposits go to pending and only become available on a Settle
+    pub pending: bool,
+    // when set, transactions of any other type are skipped
+    pub only_types: Option<Vec<TransactionType>>,
+    // clients no transaction may touch, like the suspense account 0. Their transactions are
+    // skipped and they're never created
+    pub reserved_clients: Vec<u16>,
+    // flag clients matching known fraud patterns
+    pub fraud_heuristics: bool,
+    // release a dispute's hold once this many more transactions pass without a Resolve
+    // or Chargeback for it
+    pub auto_resolve_after: Option<usize>,
+    // print every applied transaction's client balances before and after to stderr
+    pub trace: bool,
+    // stop with an error as soon as a client's balances stop adding up
+    pub strict_invariants: bool,
+    // stop with an error on a reused transaction id, instead of skipping the row
+    pub strict: bool,
+    // a resolved dispute can't be opened again, so resolved and charged back transactions
+    // are dropped instead of kept for the rest of the run
+    pub no_redispute: bool,
+    // round the client's balances after every transaction applied to them
+    pub round_each: RoundEach,
+    // the places round_each rounds to, PRECISION if unset
+    pub round_precision: Option<u32>,
+    // stop with an error instead of adding a client past this many
+    pub max_clients: Option<usize>,
+    // stop with an error instead of keeping a transaction past this many
+    pub max_transactions: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Transaction {
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+
+    #[serde(rename = "client")]
+    pub client_id: u16,
+
+    #[serde(rename = "tx")]
+    pub transaction_id: u32,
+
+    // json rows for disputes, resolves and chargebacks can leave the amount out entirely.
+    // A csv amount is read by CsvTransaction instead
+    #[serde(with = "rust_decimal::serde::arbitrary_precision_option", default)]
+    pub amount: Option<Decimal>,
+
+    // bool::default is false
+    #[serde(default)]
+    pub disputed: bool,
+
+    // the client that opened the dispute on this transaction, never part of the csv
+    #[serde(skip)]
+    disputed_by: Option<u16>,
+
+    // how much of the amount the open dispute holds, when the dispute row asked for less
+    // than all of it
+    #[serde(skip)]
+    disputed_amount: Option<Decimal>,
+
+    // optional columns some feeds carry, only used for --group-by
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub segment: Option<String>,
+
+    // the client a transfer pays, every other type leaves it empty
+    #[serde(default)]
+    pub destination: Option<u16>,
+}
+
+impl Transaction {
+    pub fn new(
+        transaction_type: TransactionType,
+        client_id: u16,
+        transaction_id: u32,
+        amount: Option<Decimal>,
+    ) -> Self {
+        Self {
+            transaction_type,
+            client_id,
+            transaction_id,
+            amount,
+            disputed: false,
+            disputed_by: None,
+            disputed_amount: None,
+            region: None,
+            segment: None,
+            destination: None,
+        }
+    }
+
+    pub fn deposit(client_id: u16, transaction_id: u32, amount: Decimal) -> Self {
+        Self::new(
+            TransactionType::Deposit,
+            client_id,
+            transaction_id,
+            Some(amount),
+        )
+    }
+
+    pub fn withdrawal(client_id: u16, transaction_id: u32, amount: Decimal) -> Self {
+        Self::new(
+            TransactionType::Withdrawal,
+            client_id,
+            transaction_id,
+            Some(amount),
+        )
+    }
+
+    // meta transactions reference another transaction by id and carry no amount
+    pub fn dispute(client_id: u16, transaction_id: u32) -> Self {
+        Self::new(TransactionType::Dispute, client_id, transaction_id, None)
+    }
+
+    pub fn resolve(client_id: u16, transaction_id: u32) -> Self {
+        Self::new(TransactionType::Resolve, client_id, transaction_id, None)
+    }
+
+    pub fn chargeback(client_id: u16, transaction_id: u32) -> Self {
+        Self::new(TransactionType::Chargeback, client_id, transaction_id, None)
+    }
+
+    pub fn transfer(
+        client_id: u16,
+        transaction_id: u32,
+        destination: u16,
+        amount: Decimal,
+    ) -> Self {
+        Self {
+            destination: Some(destination),
+            ..Self::new(
+                TransactionType::Transfer,
+                client_id,
+                transaction_id,
+                Some(amount),
+            )
+        }
+    }
+
+    fn amount(&self) -> Result<Decimal, TransactionError> {
+        self.amount.ok_or(TransactionError::MissingAmount {
+            tx: self.transaction_id,
+        })
+    }
+
+    // what a resolve or chargeback of this transaction's dispute moves, the part the
+    // dispute held
+    fn held_amount(&self) -> Result<Decimal, TransactionError> {
+        self.disputed_amount.map_or_else(|| self.amount(), Ok)
+    }
+}
+
+/*
+Builds a Transaction from a record with the columns in the usual order: type, client, tx,
+amount, then the optional region, segment and destination. Unlike going through serde this doesn't
+need headers, and each field that fails to parse is named in the error.
+*/
+impl TryFrom<&csv::StringRecord> for Transaction {
+    type Error = anyhow::Error;
+
+    fn try_from(record: &csv::StringRecord) -> Result<Self> {
+        let field = |index: usize, name: &str| {
+            record
+                .get(index)
+                .map(str::trim)
+                .ok_or_else(|| anyhow!("Missing {} field in record: {:?}", name, record))
+        };
+        // the optional columns, empty counts as missing
+        let optional = |index: usize| {
+            record
+                .get(index)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        let transaction_type = parse_transaction_type(field(0, "type")?)
+            .with_context(|| format!("Invalid type field in record: {:?}", record))?;
+        let client_id = field(1, "client")?
+            .parse()
+            .with_context(|| format!("Invalid client field in record: {:?}", record))?;
+        let transaction_id = field(2, "tx")?
+            .parse()
+            .with_context(|| format!("Invalid tx field in record: {:?}", record))?;
+        let amount = optional(3)
+            .map(Decimal::from_str)
+            .transpose()
+            .with_context(|| format!("Invalid amount field in record: {:?}", record))?;
+
+        let mut transaction = Transaction::new(transaction_type, client_id, transaction_id, amount);
+        transaction.region = optional(4).map(str::to_string);
+        transaction.segment = optional(5).map(str::to_string);
+        transaction.destination = optional(6)
+            .map(str::parse)
+            .transpose()
+            .with_context(|| format!("Invalid destination field in record: {:?}", record))?;
+        Ok(transaction)
+    }
+}
+
+// type, client, tx, amount, region, segment and destination
+const MAX_FIELDS: usize = 7;
+
+/*
+Parses one csv line without a header, e.g. `deposit,1,1,10.0` or `dispute,1,1`, with the
+columns in the same order as the TryFrom above. Quoted fields aren't supported, this is for
+transactions typed in by hand rather than read from a file.
+*/
+impl FromStr for Transaction {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let record = csv::StringRecord::from(line.trim().split(',').collect::<Vec<_>>());
+        if record.len() > MAX_FIELDS {
+            return Err(anyhow!(
+                "Expected at most {} fields, found {} in: {:?}",
+                MAX_FIELDS,
+                record.len(),
+                line
+            ));
+        }
+        Transaction::try_from(&record)
+    }
+}
+
+// What PaymentsEngine::apply_all does about a transaction that isn't applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorMode {
+    // return it straight away, leaving the rest unapplied
+    Stop,
+    // carry on with the next one, and return them all at the end
+    Collect,
+}
+
+// Whether handle_transaction changed any balances for a row, or ignored it
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Applied,
+    Skipped,
+}
+
+// FxHash rather than the default SipHash: the keys are small integers from our own input,
+// so there's no flooding to guard against, and there's a lookup on every row
+pub type TransactionList = FxHashMap<u32, Transaction>;
+pub type ClientList = FxHashMap<u16, Client>;
+
+/*
+The payments engine: every client's balances, the transactions they can still dispute
+and the options transactions are applied with. Transactions go in one at a time with
+`process`, or straight from a csv with `process_transactions`.
+*/
+#[derive(Debug, Default)]
+pub struct PaymentsEngine {
+    // the clients and transactions the handlers work on
+    memory: MemoryStore,
+    options: Options,
+    // disputes still waiting on a Resolve or Chargeback, oldest first, with the row they
+    // were opened on. Only tracked for auto_resolve_after
+    open_disputes: VecDeque<(usize, u32)>,
+    // clients dropped by a CloseClient, later rows for them are rejected rather than
+    // starting a new client under the same id
+    closed: HashSet<u16>,
+    // the client the last row closed, only until the next row, see PaymentsEngine::client
+    just_closed: Option<Client>,
+    rows: usize,
+    stats: Stats,
+    // where the clients and transactions are saved as they change, nowhere by default
+    store: Option<Box<dyn TransactionStore>>,
+}
+
+impl PaymentsEngine {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    // An engine that carries on from everything saved in the store, and saves to it as
+    // transactions are applied
+    pub fn with_store(options: Options, mut store: Box<dyn TransactionStore>) -> Result<Self> {
+        let mut engine = Self::new(options);
+        store.load(&mut engine.memory.clients, &mut engine.memory.transactions)?;
+        engine.store = Some(store);
+        Ok(engine)
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    // A copy of every client and transaction, to restore from later. Doesn't include the
+    // store, options or stats
+    pub fn snapshot(&self) -> EngineState {
+        EngineState {
+            clients: self.memory.clients.clone(),
+            transactions: self.memory.transactions.clone(),
+        }
+    }
+
+    // An engine with default options that carries on from a snapshot
+    pub fn restore(state: EngineState) -> Self {
+        let mut engine = Self::default();
+        engine.memory.clients = state.clients;
+        engine.memory.transactions = state.transactions;
+        engine
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /*
+    Applies one transaction, answering with the ids of the clients it changed, so a caller
+    showing balances only has to refresh those: its client, a transfer's destination too,
+    and anyone whose dispute --auto-resolve-after released on the way.
+
+    A row the spec ignores isn't an error here, it changes no balances of its own so it
+    answers with only the clients an auto resolve released, none for most. Though it can
+    still have added its client with nothing in it. Only a fatal error, one that leaves the
+    engine unable to carry on (see TransactionError::is_fatal), is returned. apply_all says
+    why a row was skipped.
+    */
+    pub fn process(&mut self, transaction: Transaction) -> Result<Vec<u16>, TransactionError> {
+        match self.process_row(transaction)? {
+            (Err(error), _) if error.is_fatal() => Err(error),
+            (_, affected) => Ok(affected),
+        }
+    }
+
+    // The row's own result, and the clients it changed either way. The outer error is
+    // one the engine couldn't carry on from while handling it
+    fn process_row(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(Result<(), TransactionError>, Vec<u16>), TransactionError> {
+        let row = self.rows;
+        self.rows += 1;
+        self.just_closed = None;
+        let transaction_type = transaction.transaction_type.clone();
+        let transaction_id = transaction.transaction_id;
+        let client_id = transaction.client_id;
+        let amount = transaction.amount;
+        let destination = transaction.destination;
+        let closed = [Some(client_id), destination]
+            .into_iter()
+            .flatten()
+            .find(|id| self.closed.contains(id));
+        let result = match closed {
+            Some(client) => Err(TransactionError::ClientClosed { client }),
+            None => handle_transaction(transaction, &mut self.memory, &self.options),
+        };
+        let mut auto_resolved = Vec::new();
+        self.stats.record(&transaction_type, amount, &result);
+        match &result {
+            Ok(()) => debug!(
+                tx = transaction_id,
+                client = client_id,
+                "applied {:?}",
+                transaction_type
+            ),
+            Err(error) => warn!(
+                tx = transaction_id,
+                client = client_id,
+                reason = %error,
+                "skipped {:?}",
+                transaction_type
+            ),
+        }
+
+        if let Some(after) = self.options.auto_resolve_after {
+            match (&transaction_type, &result) {
+                (TransactionType::Dispute, Ok(())) => {
+                    self.open_disputes.push_back((row, transaction_id))
+                }
+                (TransactionType::Resolve | TransactionType::Chargeback, Ok(())) => {
+                    self.open_disputes.retain(|&(_, id)| id != transaction_id)
+                }
+                _ => {}
+            }
+            while let Some(&(opened_on, id)) = self.open_disputes.front() {
+                if row - opened_on < after {
+                    break;
+                }
+                self.open_disputes.pop_front();
+                debug!(
+                    tx = id,
+                    "auto resolving a dispute left open for {} rows", after
+                );
+                // looked up first, --no-redispute drops the transaction while resolving it
+                let disputed_by = self
+                    .memory
+                    .transactions
+                    .get(&id)
+                    .and_then(|transaction| transaction.disputed_by);
+                match auto_resolve(id, &mut self.memory, &self.options) {
+                    Ok(()) => {
+                        auto_resolved.extend(disputed_by);
+                        self.save(disputed_by, id)?;
+                        if self.options.no_redispute {
+                            self.remove_transaction(id)?;
+                        }
+                    }
+                    Err(error) if error.is_fatal() => return Err(error),
+                    // nothing was released, but the row itself was still handled
+                    Err(error) => warn!(tx = id, reason = %error, "couldn't auto resolve"),
+                }
+            }
+        }
+        // a skipped row can still have added its client
+        self.save(Some(client_id), transaction_id)?;
+        if transaction_type == TransactionType::Transfer && result.is_ok() {
+            self.save(destination, transaction_id)?;
+        }
+        if self.options.no_redispute
+            && matches!(
+                transaction_type,
+                TransactionType::Resolve | TransactionType::Chargeback
+            )
+            && result.is_ok()
+        {
+            self.remove_transaction(transaction_id)?;
+        }
+        if transaction_type == TransactionType::CloseClient && result.is_ok() {
+            self.close_client(client_id)?;
+        }
+        let mut affected = Vec::new();
+        if result.is_ok() {
+            affected.push(client_id);
+            if transaction_type == TransactionType::Transfer {
+                affected.extend(destination);
+            }
+        }
+        for id in auto_resolved {
+            if !affected.contains(&id) {
+                affected.push(id);
+            }
+        }
+        Ok((result, affected))
+    }
+
+    // Saves the client and transaction to the store, if there is one. Without a client
+    // id, the client the transaction belongs to
+    fn save(
+        &mut self,
+        client_id: Option<u16>,
+        transaction_id: u32,
+    ) -> Result<(), TransactionError> {
+        let store = match self.store.as_mut() {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+        let transaction = self.memory.transactions.get(&transaction_id);
+        let saved = (|| {
+            if let Some(transaction) = transaction {
+                store.save_transaction(transaction)?;
+            }
+            let client_id = client_id.or(transaction.map(|transaction| transaction.client_id));
+            if let Some(client) = client_id.and_then(|id| self.memory.clients.get(&id)) {
+                store.save_client(client)?;
+            }
+            Ok(())
+        })();
+        saved.map_err(|error: anyhow::Error| TransactionError::Store {
+            message: format!("{:#}", error),
+        })
+    }
+
+    // Drops a transaction --no-redispute pruned from memory from the store too
+    fn remove_transaction(&mut self, id: u32) -> Result<(), TransactionError> {
+        match self.store.as_mut() {
+            Some(store) => store
+                .remove_transaction(id)
+                .map_err(|error| TransactionError::Store {
+                    message: format!("{:#}", error),
+                }),
+            None => Ok(()),
+        }
+    }
+
+    // Same as process, but answering whether the row was applied or skipped
+    pub fn apply(&mut self, transaction: Transaction) -> Result<Outcome, TransactionError> {
+        match self.process_row(transaction)?.0 {
+            Ok(()) => Ok(Outcome::Applied),
+            Err(error) if error.is_fatal() => Err(error),
+            Err(
+                error @ (TransactionError::DuplicateTransaction { .. }
+                | TransactionError::UnknownTransaction { .. }),
+            ) if self.options.strict => Err(error),
+            Err(_) => Ok(Outcome::Skipped),
+        }
+    }
+
+    /*
+    Applies every transaction in order, for callers that have them all at hand.
+
+    With ErrorMode::Stop the first transaction that isn't applied is returned as the error
+    and nothing after it is applied. With ErrorMode::Collect every row is tried and the
+    reasons the skipped ones weren't applied are returned, only a fatal error stops early.
+    */
+    pub fn apply_all<I: IntoIterator<Item = Transaction>>(
+        &mut self,
+        transactions: I,
+        mode: ErrorMode,
+    ) -> Result<Vec<TransactionError>, TransactionError> {
+        let mut skipped = Vec::new();
+        for transaction in transactions {
+            match self.process_row(transaction)?.0 {
+                Ok(()) => {}
+                Err(error) if error.is_fatal() || mode == ErrorMode::Stop => return Err(error),
+                Err(error) => skipped.push(error),
+            }
+        }
+        Ok(skipped)
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.memory.clients.values()
+    }
+
+    // A client closed by the row just applied is gone from clients(), but can still be read
+    // here until the next row, so whatever reports on that row sees them as they were closed
+    pub fn client(&self, id: u16) -> Option<&Client> {
+        self.memory
+            .clients
+            .get(&id)
+            .or(self.just_closed.as_ref().filter(|client| client.id == id))
+    }
+
+    pub fn transaction(&self, id: u32) -> Option<&Transaction> {
+        self.memory.transactions.get(&id)
+    }
+
+    /// Whether the transaction with this id is currently under dispute, false for ids the
+    /// engine hasn't seen.
+    ///
+    /// ```
+    /// use rust_decimal_macros::dec;
+    /// use toy_marketplace::{PaymentsEngine, Transaction};
+    ///
+    /// let mut engine = PaymentsEngine::default();
+    /// engine.process(Transaction::deposit(1, 1, dec!(10))).unwrap();
+    /// engine.process(Transaction::dispute(1, 1)).unwrap();
+    /// assert!(engine.is_disputed(1));
+    ///
+    /// engine.process(Transaction::resolve(1, 1)).unwrap();
+    /// assert!(!engine.is_disputed(1));
+    /// assert!(!engine.is_disputed(2));
+    /// ```
+    pub fn is_disputed(&self, id: u32) -> bool {
+        self.transaction(id)
+            .is_some_and(|transaction| transaction.disputed)
+    }
+
+    // Starting balances from a csv, see seed::load_clients
+    pub fn load_clients<R: io::Read>(&mut self, source: R) -> Result<Vec<u16>> {
+        let conflicts = seed::load_clients(&mut self.memory.clients, source)?;
+        if let Some(store) = self.store.as_mut() {
+            for client in self.memory.clients.values() {
+                store.save_client(client)?;
+            }
+        }
+        Ok(conflicts)
+    }
+
+    // Drops a client once their CloseClient is applied, from the store too. Any later row
+    // for them is rejected rather than starting a new client under the same id
+    fn close_client(&mut self, id: u16) -> Result<(), TransactionError> {
+        self.just_closed = self.memory.clients.remove(&id);
+        self.closed.insert(id);
+        match self.store.as_mut() {
+            Some(store) => store
+                .remove_client(id)
+                .map_err(|error| TransactionError::Store {
+                    message: format!("{:#}", error),
+                }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn handle_transaction(
+    transaction: Transaction,
+    store: &mut impl Store,
+    options: &Options,
+) -> Result<(), TransactionError> {
+    if let Some(only_types) = &options.only_types {
+        if !only_types.contains(&transaction.transaction_type) {
+            return Err(TransactionError::Excluded {
+                tx: transaction.transaction_id,
+            });
+        }
+    }
+
+    // checked before the client is added below, a reserved one must never exist
+    let reserved = std::iter::once(transaction.client_id)
+        .chain(match transaction.transaction_type {
+            TransactionType::Transfer => transaction.destination,
+            _ => None,
+        })
+        .find(|id| options.reserved_clients.contains(id));
+    if let Some(client) = reserved {
+        return Err(TransactionError::ReservedClient { client });
+    }
+
+    /*
+    We always want to add the client from the transaction to the client list, even when the
+    transaction is then ignored. Except for a dispute, resolve or chargeback: a new client
+    can't own the transaction it references, so it's only added below once the row has
+    actually been applied, a typo in a tx id mustn't leave behind an empty client.
+    */
+    let kind = TransactionKind::from(&transaction.transaction_type);
+    let mut client = match store.get_client(transaction.client_id) {
+        Some(client) => client,
+        None => {
+            let client = if options.pending {
+                Client::with_pending(transaction.client_id)
+            } else {
+                Client::new(transaction.client_id)
+            };
+            if !matches!(kind, TransactionKind::Meta(_)) {
+                check_client_limit(transaction.transaction_id, store, options)?;
+                store.upsert_client(client.clone());
+            }
+            client
+        }
+    };
+
+    // A charged back account is frozen, nothing but closing it touches it anymore
+    if client.locked && transaction.transaction_type != TransactionType::CloseClient {
+        return Err(TransactionError::AccountLocked { client: client.id });
+    }
+
+    let before = options.trace.then(|| (transaction.clone(), client.clone()));
+    let checked = options.strict_invariants.then(|| transaction.clone());
+    match kind {
+        TransactionKind::Standard(standard_type) => {
+            handle_standard_transaction(standard_type, transaction, &mut client, store, options)?
+        }
+        TransactionKind::Meta(meta_type) => {
+            handle_meta_transaction(meta_type, transaction, &mut client, store, options)?
+        }
+        // Settling doesn't reference or create a transaction, it only touches the client
+        TransactionKind::Settle => client.settle().ok_or(TransactionError::Overflow {
+            client: client.id,
+            tx: transaction.transaction_id,
+        })?,
+        // PaymentsEngine drops the client once the row is applied
+        TransactionKind::CloseClient => {}
+        TransactionKind::Transfer => handle_transfer(&transaction, &mut client, store, options)?,
+    };
+    round_balances(&mut client, options);
+    store.upsert_client(client.clone());
+
+    if let Some((transaction, before)) = before {
+        tracing::info!(target: "trace", "{}", trace_line(&transaction, &before, &client));
+    }
+    if let Some(transaction) = &checked {
+        check_invariants(&client, transaction)?;
+    }
+    Ok(())
+}
+
+/*
+The balances should always add up: available + held (+ pending) == total. If they don't,
+there's a bug in how a transaction was applied, and with --strict-invariants the run stops
+there instead of writing balances that are wrong.
+*/
+fn check_invariants(client: &Client, transaction: &Transaction) -> Result<(), TransactionError> {
+    if !client.is_consistent() {
+        return Err(TransactionError::InvariantViolated {
+            client: client.id,
+            tx: transaction.transaction_id,
+            transaction_type: transaction.transaction_type.clone(),
+            available: client.available_amount,
+            held: client.held_amount,
+            pending: client.pending_amount.unwrap_or_default(),
+            total: client.total_amount,
+        });
+    }
+    Ok(())
+}
+
+// What --trace logs for an applied transaction: the client's balances either side of it.
+// Logged with the target "trace", which the binary shows on stderr for --trace
+fn trace_line(transaction: &Transaction, before: &Client, after: &Client) -> String {
+    let balances = |client: &Client| {
+        format!(
+            "available={} held={} total={} locked={}",
+            client.available_amount, client.held_amount, client.total_amount, client.locked
+        )
+    };
+    format!(
+        "{:?} tx {} client {}: {} -> {}",
+        transaction.transaction_type,
+        transaction.transaction_id,
+        transaction.client_id,
+        balances(before),
+        balances(after)
+    )
+}
+
+// Moves the funds of a deposit, withdrawal or fee. `client` is only saved if it succeeds
+fn handle_standard_transaction(
+    standard_type: StandardType,
+    transaction: Transaction,
+    client: &mut Client,
+    store: &mut impl Store,
+    options: &Options,
+) -> Result<(), TransactionError> {
+    let transaction_id = transaction.transaction_id;
+    let amount = transaction.amount()?;
+    // Every standard transaction moves a strictly positive amount, a negative deposit
+    // would really be a withdrawal that skips the funds check
+    if amount <= dec!(0) {
+        return Err(TransactionError::NonPositiveAmount { tx: transaction_id });
+    }
+    // Ids are unique, a second deposit with the same one can't replace the first since
+    // disputes of that id have to keep meaning the original
+    if store.get_transaction(transaction_id).is_some() {
+        return Err(TransactionError::DuplicateTransaction { tx: transaction_id });
+    }
+    if let Some(max) = options.max_transactions {
+        if standard_type == StandardType::Deposit && store.transaction_count() >= max {
+            return Err(TransactionError::TooManyTransactions {
+                tx: transaction_id,
+                max,
+            });
+        }
+    }
+
+    let client_id = client.id;
+    let overflow = || TransactionError::Overflow {
+        client: client_id,
+        tx: transaction_id,
+    };
+    match standard_type {
+        StandardType::Deposit => {
+            if options.pending {
+                client.deposit_pending(amount).ok_or_else(overflow)?
+            } else if options.saturate {
+                client.saturating_deposit(amount)
+            } else {
+                client.deposit(amount).ok_or_else(overflow)?
+            }
+        }
+        StandardType::Withdrawal => {
+            // Insufficient available funds fail the withdrawal and leave the account as is,
+            // held funds can't be withdrawn either. Only the overdraft can take it negative
+            let within_overdraft = client
+                .available_amount
+                .checked_sub(amount)
+                .is_some_and(|after| after >= -options.overdraft);
+            if !within_overdraft {
+                return Err(TransactionError::InsufficientFunds {
+                    client: client.id,
+                    tx: transaction_id,
+                });
+            }
+            if options.saturate {
+                client.saturating_withdraw(amount)
+            } else {
+                client.withdraw(amount).ok_or_else(overflow)?
+            }
+        }
+        StandardType::Fee => {
+            // A fee the client can't cover is dropped rather than taking them negative
+            if amount > client.available_amount {
+                return Err(TransactionError::InsufficientFunds {
+                    client: client.id,
+                    tx: transaction_id,
+                });
+            }
+            if options.saturate {
+                client.saturating_withdraw(amount)
+            } else {
+                client.withdraw(amount).ok_or_else(overflow)?
+            }
+        }
+    }
+
+    // Only deposits can be disputed, so they're the only transactions worth keeping around.
+    // Withdrawals and fees make up a big share of large inputs and would otherwise sit in
+    // memory for the rest of the run
+    if standard_type == StandardType::Deposit {
+        store.insert_transaction(transaction);
+    }
+    Ok(())
+}
+
+/*
+Moves available funds from `client` to the transaction's destination, who is added if they
+don't exist yet. Either both sides change or neither does: every check is made before
+anything moves, and `client` is only saved if it succeeds.
+
+A transfer can't be disputed, so like a withdrawal it isn't kept.
+*/
+fn handle_transfer(
+    transaction: &Transaction,
+    client: &mut Client,
+    store: &mut impl Store,
+    options: &Options,
+) -> Result<(), TransactionError> {
+    let transaction_id = transaction.transaction_id;
+    let amount = transaction.amount()?;
+    let destination = transaction
+        .destination
+        .ok_or(TransactionError::MissingDestination { tx: transaction_id })?;
+    if amount <= dec!(0) {
+        return Err(TransactionError::NonPositiveAmount { tx: transaction_id });
+    }
+    if store.get_transaction(transaction_id).is_some() {
+        return Err(TransactionError::DuplicateTransaction { tx: transaction_id });
+    }
+    // it would move nothing, and is more likely a mistake in the feed than meant
+    if destination == client.id {
+        return Err(TransactionError::SelfTransfer { tx: transaction_id });
+    }
+    if amount > client.available_amount {
+        return Err(TransactionError::InsufficientFunds {
+            client: client.id,
+            tx: transaction_id,
+        });
+    }
+    // the funds were already settled with the sender, so they're available straight away
+    // even with --pending
+    let mut receiver = match store.get_client(destination) {
+        Some(receiver) if receiver.locked => {
+            return Err(TransactionError::AccountLocked {
+                client: destination,
+            })
+        }
+        Some(receiver) => receiver,
+        None => {
+            check_client_limit(transaction_id, store, options)?;
+            if options.pending {
+                Client::with_pending(destination)
+            } else {
+                Client::new(destination)
+            }
+        }
+    };
+
+    if options.saturate {
+        client.saturating_withdraw(amount);
+        receiver.saturating_deposit(amount);
+    } else {
+        client.withdraw(amount).ok_or(TransactionError::Overflow {
+            client: client.id,
+            tx: transaction_id,
+        })?;
+        receiver.deposit(amount).ok_or(TransactionError::Overflow {
+            client: destination,
+            tx: transaction_id,
+        })?;
+    }
+    round_balances(&mut receiver, options);
+    if options.strict_invariants {
+        check_invariants(&receiver, transaction)?;
+    }
+    store.upsert_client(receiver);
+    Ok(())
+}
+
+// Disputes, resolves or charges back a deposit of the client. `client` is only saved if it
+// succeeds
+fn handle_meta_transaction(
+    meta_type: MetaType,
+    transaction: Transaction,
+    client: &mut Client,
+    store: &mut impl Store,
+    options: &Options,
+) -> Result<(), TransactionError> {
+    let transaction_id = transaction.transaction_id;
+    let mut target_transaction = store
+        .get_transaction(transaction_id)
+        .ok_or(TransactionError::UnknownTransaction { tx: transaction_id })?;
+    let client_id = client.id;
+    let overflow = || TransactionError::Overflow {
+        client: client_id,
+        tx: transaction_id,
+    };
+
+    /*
+    Only deposits can be disputed. The money of a withdrawal already left, holding it again
+    would take it out of available a second time. Withdrawals aren't kept in the list, so
+    they already end up as unknown transactions above, this makes sure nothing else does.
+    */
+    if target_transaction.transaction_type != TransactionType::Deposit {
+        return Err(TransactionError::NotADeposit { tx: transaction_id });
+    }
+
+    // A client can only dispute their own transactions, never someone else's. Since only the
+    // owner opens a dispute, only they get to resolve or charge it back too
+    if target_transaction.client_id != transaction.client_id {
+        return Err(TransactionError::ClientMismatch {
+            tx: transaction_id,
+            client: transaction.client_id,
+            owner: target_transaction.client_id,
+        });
+    }
+
+    match meta_type {
+        MetaType::Dispute => {
+            // a second dispute of the same transaction would hold its funds twice
+            if target_transaction.disputed {
+                return Err(TransactionError::AlreadyDisputed { tx: transaction_id });
+            }
+            let full_amount = target_transaction.amount()?;
+            // the dispute row's own amount disputes only that much of the transaction
+            let amount = match transaction.amount {
+                Some(amount) if amount <= dec!(0) => {
+                    return Err(TransactionError::NonPositiveAmount { tx: transaction_id })
+                }
+                Some(amount) if amount > full_amount => {
+                    return Err(TransactionError::DisputeExceedsAmount { tx: transaction_id })
+                }
+                Some(amount) => amount,
+                None => full_amount,
+            };
+            target_transaction.disputed = true;
+            target_transaction.disputed_by = Some(transaction.client_id);
+            target_transaction.disputed_amount = (amount < full_amount).then_some(amount);
+            let available_before = client.available_amount;
+            if options.saturate {
+                client.saturating_hold(amount)
+            } else {
+                client.hold(amount).ok_or_else(overflow)?
+            }
+
+            /*
+            Deposit, withdraw the same money, then dispute the deposit: the hold has
+            nothing left to take from, so it drives available negative. If the dispute
+            is later resolved in the client's favor, they've been paid twice.
+            */
+            if options.fraud_heuristics
+                && available_before >= dec!(0)
+                && client.available_amount < dec!(0)
+            {
+                client.suspected_fraud = true;
+            }
+        }
+        MetaType::Resolve => {
+            if !target_transaction.disputed {
+                return Err(TransactionError::NotDisputed { tx: transaction_id });
+            }
+            let amount = target_transaction.held_amount()?;
+            if options.saturate {
+                client.saturating_release(amount)
+            } else {
+                client.release(amount).ok_or_else(overflow)?
+            }
+            // back to how it was before the dispute, so it can be disputed again. A charged
+            // back transaction stays disputed, the client is locked after it anyway
+            target_transaction.disputed = false;
+            target_transaction.disputed_by = None;
+            target_transaction.disputed_amount = None;
+        }
+
+        MetaType::Chargeback => {
+            if !target_transaction.disputed {
+                return Err(TransactionError::NotDisputed { tx: transaction_id });
+            }
+            let amount = target_transaction.held_amount()?;
+            if options.saturate {
+                client.saturating_chargeback(amount)
+            } else {
+                client.chargeback(amount).ok_or_else(overflow)?
+            }
+            client.freeze();
+        }
+    };
+    match meta_type {
+        // nothing can act on it anymore: a resolved dispute can't be reopened and a charged
+        // back client is locked
+        MetaType::Resolve | MetaType::Chargeback if options.no_redispute => {
+            store.remove_transaction(transaction_id)
+        }
+        _ => store.insert_transaction(target_transaction),
+    }
+    Ok(())
+}
+
+// Whether the read loop got through the whole input or was asked to stop part way
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadOutcome {
+    Finished,
+    Stopped,
+}
+
+// The formats transactions can be read in
+#[derive(clap::ValueEnum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InputFormat {
+    Csv,
+    // one json object per line, with the same field names as the csv header
+    Json,
+}
+
+// Deserializes the transactions in `reader` one at a time, as they're asked for
+pub fn read_transactions<'a, R: io::Read + 'a>(
+    reader: R,
+    format: InputFormat,
+) -> Box<dyn Iterator<Item = Result<Transaction>> + 'a> {
+    read_transactions_with_delimiter(reader, format, b',')
+}
+
+// read_transactions for csv that separates its fields with `delimiter`, e.g. b'\t' for tsv
+pub fn read_transactions_with_delimiter<'a, R: io::Read + 'a>(
+    reader: R,
+    format: InputFormat,
+    delimiter: u8,
+) -> Box<dyn Iterator<Item = Result<Transaction>> + 'a> {
+    match format {
+        // flexible so rows with extra trailing columns (metadata some feeds append after
+        // amount) are read instead of failing the run, the extra values are ignored.
+        // Trimmed since `1, 2, 5.0` doesn't parse as numbers with the spaces left in. The
+        // csv reader already skips the byte order mark Excel starts its exports with.
+        // Fields are matched to Transaction by the header's names, never by position, so
+        // the columns can come in any order
+        InputFormat::Csv => {
+            let mut reader = ReaderBuilder::new()
+                .has_headers(true)
+                .flexible(true)
+                .trim(Trim::All)
+                .delimiter(delimiter)
+                .from_reader(reader);
+            // checked before any row, otherwise a misnamed column only shows up as every
+            // row failing to deserialize
+            let header = reader
+                .headers()
+                .map_err(anyhow::Error::from)
+                .and_then(|header| Ok(check_header(header)?));
+            match header {
+                Err(error) => Box::new(std::iter::once(Err(error))),
+                Ok(()) => Box::new(
+                    reader
+                        .into_deserialize::<CsvTransaction>()
+                        .map(|result| Transaction::try_from(result?)),
+                ),
+            }
+        }
+        // line by line, so a malformed line doesn't stop the lines after it being read
+        InputFormat::Json => Box::new(
+            io::BufRead::lines(io::BufReader::new(reader))
+                .enumerate()
+                .map(|(number, line)| match line {
+                    // serde_json doesn't skip a byte order mark itself
+                    Ok(line) if number == 0 => Ok(line.trim_start_matches('\u{feff}').to_string()),
+                    line => line,
+                })
+                .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+                .map(|line| {
+                    let transaction: Transaction = serde_json::from_str(&line?)?;
+                    Ok(transaction)
+                }),
+        ),
+    }
+}
+
+/*
+A csv row as it's read, with the amount kept as written. Handed to serde the csv reader
+reads a number as an f64 first, so an amount with more than 15 or so significant digits
+like 12345678901234.5678 would lose its last places, and one too large for an f64 wouldn't
+read at all. Decimal::from_str parses it exactly.
+*/
+#[derive(Deserialize)]
+struct CsvTransaction {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    #[serde(rename = "client")]
+    client_id: u16,
+    #[serde(rename = "tx")]
+    transaction_id: u32,
+    #[serde(default)]
+    amount: Option<String>,
+    #[serde(default)]
+    disputed: bool,
+    #[serde(default)]
+    region: Option<String>,
+    #[serde(default)]
+    segment: Option<String>,
+    #[serde(default)]
+    destination: Option<u16>,
+}
+
+impl TryFrom<CsvTransaction> for Transaction {
+    type Error = anyhow::Error;
+
+    fn try_from(row: CsvTransaction) -> Result<Self> {
+        let amount = row
+            .amount
+            .as_deref()
+            .filter(|amount| !amount.is_empty())
+            .map(Decimal::from_str)
+            .transpose()
+            .with_context(|| format!("Invalid amount field in row: {:?}", row.amount))?;
+        let mut transaction = Transaction::new(
+            row.transaction_type,
+            row.client_id,
+            row.transaction_id,
+            amount,
+        );
+        transaction.disputed = row.disputed;
+        transaction.region = row.region;
+        transaction.segment = row.segment;
+        transaction.destination = row.destination;
+        Ok(transaction)
+    }
+}
+
+// the columns a csv of transactions has to have, any others are ignored
+const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+// An empty input has no header, and no rows to go with it either
+fn check_header(header: &csv::StringRecord) -> Result<(), InputError> {
+    if header.is_empty() {
+        return Ok(());
+    }
+    match REQUIRED_COLUMNS
+        .into_iter()
+        .find(|column| !header.iter().any(|name| name == *column))
+    {
+        Some(column) => Err(InputError::MissingColumn { column }),
+        None => Ok(()),
+    }
+}
+
+// A row that couldn't be read, and was left out of the run
+#[derive(Debug, PartialEq, Eq)]
+pub struct RowError {
+    // counting from 1, across every input
+    pub row: usize,
+    pub message: String,
+}
+
+/*
+Leaves out the rows that couldn't be read, collecting them into `errors`, so one malformed
+row doesn't throw away the work done on every row around it. An input that can't be read
+at all (an InputError) is still passed on.
+*/
+pub fn skip_invalid_rows<'a>(
+    transactions: impl Iterator<Item = Result<Transaction>> + 'a,
+    errors: &'a mut Vec<RowError>,
+) -> impl Iterator<Item = Result<Transaction>> + 'a {
+    transactions
+        .enumerate()
+        .filter_map(move |(index, result)| match result {
+            Err(error) if error.is::<InputError>() => Some(Err(error)),
+            Err(error) => {
+                errors.push(RowError {
+                    row: index + 1,
+                    message: error.to_string(),
+                });
+                None
+            }
+            transaction => Some(transaction),
+        })
+}
+
+/*
+Applies every transaction to the engine.
+
+`should_stop` is checked before each row. Rows are applied one at a time, so stopping
+between them always leaves the engine consistent with the rows read so far.
+`on_row` is called with every row once it's been handled, for reporting that sits
+outside the engine itself.
+*/
+pub fn apply_transactions(
+    transactions: impl IntoIterator<Item = Result<Transaction>>,
+    engine: &mut PaymentsEngine,
+    mut should_stop: impl FnMut() -> bool,
+    mut on_row: impl FnMut(&Transaction, Outcome, &PaymentsEngine) -> Result<()>,
+) -> Result<ReadOutcome> {
+    for result in transactions {
+        if should_stop() {
+            return Ok(ReadOutcome::Stopped);
+        }
+        let transaction = result?;
+        let row = transaction.clone();
+        let outcome = engine.apply(transaction)?;
+        // a client this row closed can still be read, so on_row gets to write them out
+        on_row(&row, outcome, engine)?;
+    }
+    Ok(ReadOutcome::Finished)
+}
+
+// apply_transactions for a csv
+pub fn process_transactions<R: io::Read>(
+    reader: R,
+    engine: &mut PaymentsEngine,
+    should_stop: impl FnMut() -> bool,
+    on_row: impl FnMut(&Transaction, Outcome, &PaymentsEngine) -> Result<()>,
+) -> Result<ReadOutcome> {
+    apply_transactions(
+        read_transactions(reader, InputFormat::Csv),
+        engine,
+        should_stop,
+        on_row,
+    )
+}
+
+// Applies everything in a csv to the engine, for callers that don't need to stop early or
+// report on each row
+pub fn process_reader<R: io::Read>(reader: R, engine: &mut PaymentsEngine) -> Result<()> {
+    process_transactions(reader, engine, || false, |_, _, _| Ok(()))?;
+    Ok(())
+}
+
+// Releases the hold of a dispute nobody resolved or charged back in time, like a Resolve
+// from the client that opened it
+fn auto_resolve(
+    transaction_id: u32,
+    store: &mut impl Store,
+    options: &Options,
+) -> Result<(), TransactionError> {
+    let mut target_transaction = store
+        .get_transaction(transaction_id)
+        .ok_or(TransactionError::UnknownTransaction { tx: transaction_id })?;
+    let mut client = match target_transaction.disputed_by.take() {
+        // the client can have been closed since they opened the dispute
+        Some(disputed_by) => {
+            store
+                .get_client(disputed_by)
+                .ok_or(TransactionError::UnknownClient {
+                    client: disputed_by,
+                })?
+        }
+        None => return Ok(()),
+    };
+    // frozen by a chargeback of another of their deposits, the hold stays where it is
+    if client.locked {
+        return Err(TransactionError::AccountLocked { client: client.id });
+    }
+    target_transaction.disputed = false;
+    let amount = target_transaction.held_amount()?;
+    target_transaction.disputed_amount = None;
+    if options.saturate {
+        client.saturating_release(amount)
+    } else {
+        client.release(amount).ok_or(TransactionError::Overflow {
+            client: client.id,
+            tx: transaction_id,
+        })?
+    }
+    round_balances(&mut client, options);
+    store.upsert_client(client);
+    if options.no_redispute {
+        store.remove_transaction(transaction_id);
+    } else {
+        store.insert_transaction(target_transaction);
+    }
+    Ok(())
+}
+
+// Stops a transaction that would add one client too many, see --max-clients
+fn check_client_limit(
+    transaction_id: u32,
+    store: &impl Store,
+    options: &Options,
+) -> Result<(), TransactionError> {
+    match options.max_clients {
+        Some(max) if store.client_count() >= max => Err(TransactionError::TooManyClients {
+            tx: transaction_id,
+            max,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/*
+Rounds each balance with --round-each, once it's been changed. The total is added back up
+from the rounded balances rather than rounded on its own, rounding all three could leave
+them a ten-thousandth apart.
+*/
+fn round_balances(client: &mut Client, options: &Options) {
+    let Some(strategy) = options.round_each.strategy() else {
+        return;
+    };
+    let places = options.round_precision.unwrap_or(PRECISION);
+    let round = |amount: Decimal| amount.round_dp_with_strategy(places, strategy);
+    client.available_amount = round(client.available_amount);
+    client.held_amount = round(client.held_amount);
+    client.pending_amount = client.pending_amount.map(round);
+    client.total_amount = client.total_recomputed();
+}
+
+// how many client rows write_clients writes between flushes of the writer
+const FLUSH_EVERY: usize = 1000;
+
+/*
+Writes every client as a csv row, as one json array or as a table (see table::write_table).
+Balances are rounded and padded to PRECISION
+places, or `precision` if given, or the `scale` if one is given (from --currency).
+
+Rows are written one at a time and the writer is flushed every FLUSH_EVERY of them, so with
+millions of clients the output starts arriving early instead of all at the end. Pass a
+buffered writer, anything unbuffered is written to once per field.
+*/
+pub fn write_clients<'a, W: io::Write>(
+    clients: impl IntoIterator<Item = &'a Client>,
+    output: &OutputOptions,
+    mut writer: W,
+) -> Result<()> {
+    let mut clients: Vec<&Client> = clients
+        .into_iter()
+        .filter(|client| !output.with_holds || !client.held_amount.is_zero())
+        .filter(|client| !output.skip_clients.contains(&client.id))
+        .collect();
+    if output.sort == SortOrder::Id {
+        clients.sort_unstable_by_key(|client| client.id);
+    }
+    let recomputed: Vec<Client>;
+    if output.recompute_totals {
+        recomputed = clients
+            .iter()
+            .map(|client| client.with_recomputed_total())
+            .collect();
+        clients = recomputed.iter().collect();
+    }
+    if output.format == OutputFormat::Table {
+        return table::write_table(clients, output, writer);
+    }
+    if output.format == OutputFormat::Json {
+        use serde::Serializer;
+        serde_json::Serializer::new(&mut writer)
+            .collect_seq(clients.into_iter().map(|client| client.normalized(output)))?;
+        writeln!(writer)?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    let mut writer = WriterBuilder::new()
+        .has_headers(!output.skip_header)
+        .delimiter(output.delimiter.unwrap_or(b','))
+        .from_writer(writer);
+    match &output.columns {
+        None => {
+            for (row, client) in clients.into_iter().enumerate() {
+                writer.serialize(client.normalized(output))?;
+                if (row + 1) % FLUSH_EVERY == 0 {
+                    writer.flush()?;
+                }
+            }
+        }
+        Some(columns) => {
+            if !output.skip_header {
+                writer.write_field("client")?;
+                for column in columns {
+                    writer.write_field(column.name())?;
+                }
+                writer.write_record(None::<&[u8]>)?;
+            }
+            for (row, client) in clients.into_iter().enumerate() {
+                let client = client.normalized(output);
+                writer.write_field(client.id.to_string())?;
+                for column in columns {
+                    writer.write_field(column.value(&client))?;
+                }
+                writer.write_record(None::<&[u8]>)?;
+                if (row + 1) % FLUSH_EVERY == 0 {
+                    writer.flush()?;
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::path::Path;
+
+    #[test]
+    fn handle_transaction_deposit_test() {
+        let mut store = MemoryStore::default();
+
+        let client_id = 1;
+
+        let transaction_amount = dec!(10.4752);
+
+        handle_transaction(
+            Transaction::deposit(client_id, 1, transaction_amount),
+            &mut store,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            &Client::builder()
+                .id(client_id)
+                .available(transaction_amount)
+                .total(transaction_amount)
+                .build(),
+            store.clients.get(&client_id).unwrap()
+        );
+
+        handle_transaction(
+            Transaction::deposit(client_id, 2, dec!(5.0000)),
+            &mut store,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            &Client::builder()
+                .id(client_id)
+                .available(transaction_amount + dec!(5))
+                .total(transaction_amount + dec!(5))
+                .build(),
+            store.clients.get(&client_id).unwrap()
+        );
+    }
+
+    #[test]
+    fn fee_reduces_available_and_total() {
+        let client_id = 1;
+        let mut store = MemoryStore::default();
+
+        for transaction in [
+            Transaction::deposit(client_id, 1, dec!(10)),
+            Transaction::new(TransactionType::Fee, client_id, 2, Some(dec!(1.5))),
+        ] {
+            handle_transaction(transaction, &mut store, &Options::default()).unwrap();
+        }
+
+        let client = store.clients.get(&client_id).unwrap();
+        assert_eq!(client.available_amount, dec!(8.5));
+        assert_eq!(client.total_amount, dec!(8.5));
+        // fees can't be disputed, so they aren't kept
+        assert!(!store.transactions.contains_key(&2));
+    }
+
+    #[test]
+    fn fee_larger_than_available_is_ignored() {
+        let client_id = 1;
+        let mut store = MemoryStore::default();
+
+        let results: Vec<_> = [
+            Transaction::deposit(client_id, 1, dec!(1)),
+            Transaction::new(TransactionType::Fee, client_id, 2, Some(dec!(1.0001))),
+        ]
+        .into_iter()
+        .map(|transaction| handle_transaction(transaction, &mut store, &Options::default()))
+        .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Err(TransactionError::InsufficientFunds { client: 1, tx: 2 })
+            ]
+        );
+
+        let client = store.clients.get(&client_id).unwrap();
+        assert_eq!(client.available_amount, dec!(1));
+        assert_eq!(client.total_amount, dec!(1));
+    }
+
+    #[test]
+    fn transfer_moves_available_funds_to_a_new_client() {
+        let mut store = MemoryStore::default();
+
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(10)),
+            Transaction::transfer(1, 2, 2, dec!(4.25)),
+        ] {
+            handle_transaction(transaction, &mut store, &Options::default()).unwrap();
+        }
+
+        assert_eq!(
+            store.clients[&1],
+            Client::builder()
+                .id(1)
+                .available(dec!(5.75))
+                .total(dec!(5.75))
+                .build()
+        );
+        assert_eq!(
+            store.clients[&2],
+            Client::builder()
+                .id(2)
+                .available(dec!(4.25))
+                .total(dec!(4.25))
+                .build()
+        );
+        assert!(!store.transactions.contains_key(&2));
+    }
+
+    #[test]
+    fn rejected_transfer_leaves_both_clients_unchanged() {
+        let mut store = MemoryStore::default();
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(10)),
+            Transaction::deposit(2, 2, dec!(1)),
+        ] {
+            handle_transaction(transaction, &mut store, &Options::default()).unwrap();
+        }
+        let clients = store.clients.clone();
+
+        let cases = [
+            (
+                Transaction::transfer(1, 3, 2, dec!(10.01)),
+                TransactionError::InsufficientFunds { client: 1, tx: 3 },
+            ),
+            (
+                Transaction::transfer(1, 4, 1, dec!(1)),
+                TransactionError::SelfTransfer { tx: 4 },
+            ),
+            (
+                Transaction::new(TransactionType::Transfer, 1, 5, Some(dec!(1))),
+                TransactionError::MissingDestination { tx: 5 },
+            ),
+        ];
+        for (transaction, error) in cases {
+            assert_eq!(
+                handle_transaction(transaction, &mut store, &Options::default()),
+                Err(error)
+            );
+        }
+        assert_eq!(store.clients, clients);
+
+        // a receiver that's been charged back can't be paid either
+        store.clients.get_mut(&2).unwrap().locked = true;
+        assert_eq!(
+            handle_transaction(
+                Transaction::transfer(1, 6, 2, dec!(1)),
+                &mut store,
+                &Options::default()
+            ),
+            Err(TransactionError::AccountLocked { client: 2 })
+        );
+        assert_eq!(store.clients[&1], clients[&1]);
+        assert_eq!(
+            handle_transaction(
+                Transaction::transfer(2, 7, 1, dec!(1)),
+                &mut store,
+                &Options::default()
+            ),
+            Err(TransactionError::AccountLocked { client: 2 })
+        );
+        assert_eq!(store.clients[&1], clients[&1]);
+    }
+
+    // hands the transactions in order to handle_transaction with fresh lists, returning each
+    // one's outcome. Not PaymentsEngine::apply_all, no engine is involved
+    fn handle_all(
+        transactions: impl IntoIterator<Item = Transaction>,
+        options: &Options,
+    ) -> (ClientList, Vec<Outcome>) {
+        let mut store = MemoryStore::default();
+        let outcomes = transactions
+            .into_iter()
+            .map(
+                |transaction| match handle_transaction(transaction, &mut store, options) {
+                    Ok(()) => Outcome::Applied,
+                    Err(error) if !error.is_fatal() => Outcome::Skipped,
+                    Err(error) => panic!("{}", error),
+                },
+            )
+            .collect();
+        (store.clients, outcomes)
+    }
+
+    #[test]
+    fn withdrawing_exactly_the_available_balance_empties_it() {
+        let (client_list, outcomes) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(2.5)),
+                Transaction::withdrawal(1, 2, dec!(2.5)),
+            ],
+            &Options::default(),
+        );
+        assert_eq!(outcomes[1], Outcome::Applied);
+        let client = client_list.get(&1).unwrap();
+        assert_eq!(client.available_amount, dec!(0));
+        assert_eq!(client.total_amount, dec!(0));
+    }
+
+    #[test]
+    fn withdrawing_more_than_available_leaves_the_account_untouched() {
+        let (client_list, outcomes) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(2.5)),
+                Transaction::withdrawal(1, 2, dec!(2.5001)),
+            ],
+            &Options::default(),
+        );
+        assert_eq!(outcomes[1], Outcome::Skipped);
+        let client = client_list.get(&1).unwrap();
+        assert_eq!(client.available_amount, dec!(2.5));
+        assert_eq!(client.total_amount, dec!(2.5));
+    }
+
+    #[test]
+    fn held_funds_cant_be_withdrawn() {
+        let (client_list, outcomes) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(3)),
+                Transaction::deposit(1, 2, dec!(1)),
+                Transaction::dispute(1, 1),
+                Transaction::withdrawal(1, 3, dec!(2)),
+            ],
+            &Options::default(),
+        );
+        // total is 4, but 3 of it is held
+        assert_eq!(outcomes[3], Outcome::Skipped);
+        let client = client_list.get(&1).unwrap();
+        assert_eq!(client.available_amount, dec!(1));
+        assert_eq!(client.held_amount, dec!(3));
+        assert_eq!(client.total_amount, dec!(4));
+    }
+
+    #[test]
+    fn withdrawal_can_go_negative_up_to_the_overdraft() {
+        let options = Options {
+            overdraft: dec!(5),
+            ..Options::default()
+        };
+        let (client_list, outcomes) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(10)),
+                Transaction::deposit(1, 2, dec!(3)),
+                Transaction::dispute(1, 2),
+                Transaction::withdrawal(1, 3, dec!(14)),
+                Transaction::withdrawal(1, 4, dec!(1.0001)),
+                Transaction::withdrawal(1, 5, dec!(1)),
+            ],
+            &options,
+        );
+        assert_eq!(
+            outcomes[3..],
+            [Outcome::Applied, Outcome::Skipped, Outcome::Applied]
+        );
+        let client = client_list.get(&1).unwrap();
+        assert_eq!(client.available_amount, dec!(-5));
+        assert_eq!(client.held_amount, dec!(3));
+        assert_eq!(client.total_amount, dec!(-2));
+        assert!(client.is_consistent());
+    }
+
+    #[test]
+    fn deposit_after_a_chargeback_is_ignored() {
+        let transactions = [
+            Transaction::deposit(1, 1, dec!(5)),
+            Transaction::deposit(1, 2, dec!(3)),
+            Transaction::dispute(1, 2),
+            Transaction::chargeback(1, 2),
+            Transaction::deposit(1, 3, dec!(10)),
+            Transaction::withdrawal(1, 4, dec!(1)),
+            Transaction::dispute(1, 1),
+        ];
+        let (charged_back, _) = handle_all(transactions[..4].to_vec(), &Options::default());
+        let (client_list, outcomes) = handle_all(transactions, &Options::default());
+
+        assert_eq!(outcomes[3], Outcome::Applied);
+        assert_eq!(outcomes[4..], [Outcome::Skipped; 3]);
+        let client = client_list.get(&1).unwrap();
+        assert!(client.locked);
+        assert_eq!(client, charged_back.get(&1).unwrap());
+    }
+
+    #[test]
+    fn no_redispute_forgets_resolved_and_charged_back_deposits() {
+        let transactions = [
+            Transaction::deposit(1, 1, dec!(5)),
+            Transaction::deposit(1, 2, dec!(3)),
+            Transaction::deposit(2, 3, dec!(4)),
+            Transaction::dispute(1, 1),
+            Transaction::resolve(1, 1),
+            Transaction::dispute(2, 3),
+            Transaction::chargeback(2, 3),
+        ];
+        let mut engine = PaymentsEngine::new(Options {
+            no_redispute: true,
+            ..Options::default()
+        });
+        for transaction in transactions.clone() {
+            assert_eq!(engine.apply(transaction), Ok(Outcome::Applied));
+        }
+        // only the deposit nothing happened to is still kept
+        assert_eq!(engine.memory.transactions.len(), 1);
+        assert!(engine.transaction(2).is_some());
+        assert_eq!(
+            engine.apply_all([Transaction::dispute(1, 1)], ErrorMode::Stop),
+            Err(TransactionError::UnknownTransaction { tx: 1 })
+        );
+
+        // by default a resolved deposit can be disputed again, so it's kept
+        let mut engine = PaymentsEngine::default();
+        for transaction in transactions {
+            engine.apply(transaction).unwrap();
+        }
+        assert_eq!(engine.memory.transactions.len(), 3);
+        assert_eq!(engine.process(Transaction::dispute(1, 1)), Ok(vec![1]));
+    }
+
+    #[test]
+    fn only_positive_amounts_are_applied() {
+        let (client_list, outcomes) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(0)),
+                Transaction::deposit(1, 2, dec!(-10)),
+                Transaction::deposit(1, 3, dec!(10)),
+                Transaction::withdrawal(1, 4, dec!(0)),
+                Transaction::withdrawal(1, 5, dec!(-10)),
+            ],
+            &Options::default(),
+        );
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Skipped,
+                Outcome::Skipped,
+                Outcome::Applied,
+                Outcome::Skipped,
+                Outcome::Skipped
+            ]
+        );
+        let client = client_list.get(&1).unwrap();
+        assert_eq!(client.available_amount, dec!(10));
+        assert_eq!(client.total_amount, dec!(10));
+    }
+
+    #[test]
+    fn disputes_are_ignored_when_only_deposits_and_withdrawals_are_allowed() {
+        let client_id = 1;
+        let mut store = MemoryStore::default();
+        let options = Options {
+            only_types: Some(vec![TransactionType::Deposit, TransactionType::Withdrawal]),
+            ..Options::default()
+        };
+
+        for transaction in [
+            Transaction::deposit(client_id, 1, dec!(10)),
+            Transaction::withdrawal(client_id, 2, dec!(4)),
+        ] {
+            handle_transaction(transaction, &mut store, &options).unwrap();
+        }
+        assert_eq!(
+            handle_transaction(Transaction::dispute(client_id, 1), &mut store, &options,),
+            Err(TransactionError::Excluded { tx: 1 })
+        );
+
+        let client = store.clients.get(&client_id).unwrap();
+        assert_eq!(client.available_amount, dec!(6));
+        assert_eq!(client.held_amount, dec!(0));
+        assert_eq!(client.total_amount, dec!(6));
+    }
+
+    #[test]
+    fn reserved_client_is_skipped_and_never_created() {
+        let options = Options {
+            reserved_clients: vec![0],
+            ..Options::default()
+        };
+        let (client_list, outcomes) = handle_all(
+            [
+                Transaction::deposit(0, 1, dec!(5)),
+                Transaction::deposit(1, 2, dec!(5)),
+                Transaction::transfer(1, 3, 0, dec!(2)),
+            ],
+            &options,
+        );
+
+        assert_eq!(
+            outcomes,
+            [Outcome::Skipped, Outcome::Applied, Outcome::Skipped]
+        );
+        assert!(!client_list.contains_key(&0));
+        assert_eq!(client_list[&1].available_amount, dec!(5));
+        assert_eq!(
+            handle_transaction(
+                Transaction::deposit(0, 4, dec!(1)),
+                &mut MemoryStore::default(),
+                &options
+            ),
+            Err(TransactionError::ReservedClient { client: 0 })
+        );
+    }
+
+    #[test]
+    fn transaction_types_are_read_ignoring_case_and_whitespace() {
+        let input = "\
+type,client,tx,amount
+  WITHDRAWL  ,1,1,1.0
+ChargeBack,1,2,
+ Deposit,1,3,2.0
+";
+        let types: Vec<TransactionType> = read_transactions(input.as_bytes(), InputFormat::Csv)
+            .map(|transaction| transaction.unwrap().transaction_type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                TransactionType::Withdrawal,
+                TransactionType::Chargeback,
+                TransactionType::Deposit
+            ]
+        );
+
+        let json: TransactionType = serde_json::from_str("\" Dispute \"").unwrap();
+        assert_eq!(json, TransactionType::Dispute);
+        assert!(parse_transaction_type("withdraw").is_err());
+    }
+
+    #[test]
+    fn space_padded_fields_are_read_as_numbers() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("priv/padded.csv");
+        let transactions: Vec<Transaction> =
+            read_transactions(File::open(path).unwrap(), InputFormat::Csv)
+                .map(|transaction| transaction.unwrap())
+                .collect();
+        assert_eq!(
+            transactions[..3],
+            [
+                Transaction::deposit(1, 1, dec!(5.0001)),
+                Transaction::deposit(2, 2, dec!(0.1234)),
+                Transaction::withdrawal(1, 3, dec!(1.5)),
+            ]
+        );
+        assert_eq!(transactions[3], Transaction::dispute(2, 2));
+        // the amount keeps every place it was written with
+        assert_eq!(transactions[0].amount.unwrap().scale(), 4);
+    }
+
+    #[test]
+    fn csv_amounts_past_what_an_f64_holds_are_read_exactly() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1, 12345678901234.5678
+deposit,1,2,79228162514264337593543950335
+";
+        let transactions: Vec<Transaction> = read_transactions(input.as_bytes(), InputFormat::Csv)
+            .map(|transaction| transaction.unwrap())
+            .collect();
+        assert_eq!(
+            transactions,
+            vec![
+                Transaction::deposit(1, 1, dec!(12345678901234.5678)),
+                Transaction::deposit(1, 2, Decimal::MAX),
+            ]
+        );
+    }
+
+    #[test]
+    fn input_starting_with_a_byte_order_mark_is_read_from_its_first_row() {
+        let csv = b"\xEF\xBB\xBFtype,client,tx,amount\ndeposit,1,1,2.5\n";
+        let json = b"\xEF\xBB\xBF{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":2.5}\n";
+        for (input, format) in [(&csv[..], InputFormat::Csv), (&json[..], InputFormat::Json)] {
+            let transactions: Vec<Transaction> = read_transactions(input, format)
+                .map(|transaction| transaction.unwrap())
+                .collect();
+            assert_eq!(transactions, vec![Transaction::deposit(1, 1, dec!(2.5))]);
+        }
+    }
+
+    #[test]
+    fn old_and_new_spellings_read_as_the_same_type() {
+        for (old, new) in [("withdrawl", "withdrawal"), ("ChargeBack", "chargeback")] {
+            assert_eq!(
+                parse_transaction_type(old).unwrap(),
+                parse_transaction_type(new).unwrap()
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&TransactionType::Withdrawal).unwrap(),
+            "\"withdrawal\""
+        );
+        assert_eq!(
+            serde_json::to_string(&TransactionType::Chargeback).unwrap(),
+            "\"chargeback\""
+        );
+    }
+
+    #[test]
+    fn parse_transaction_type_uses_the_csv_spelling() {
+        assert_eq!(
+            parse_transaction_type("chargeback").unwrap(),
+            TransactionType::Chargeback
+        );
+        assert_eq!(
+            parse_transaction_type(" withdrawl").unwrap(),
+            TransactionType::Withdrawal
+        );
+        assert!(parse_transaction_type("refund").is_err());
+    }
+
+    #[test]
+    fn stopping_early_still_writes_consistent_balances_for_processed_rows() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+withdrawl,1,3,2.5,
+deposit,2,4,100.0
+";
+        let mut engine = PaymentsEngine::default();
+
+        // simulate a Ctrl-C arriving after the third row has been applied
+        let mut rows_read = 0;
+        let outcome = process_transactions(
+            input.as_bytes(),
+            &mut engine,
+            || {
+                rows_read += 1;
+                rows_read > 3
+            },
+            |_, _, _| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(outcome, ReadOutcome::Stopped);
+
+        let mut output = Vec::new();
+        write_clients(engine.clients(), &OutputOptions::default(), &mut output).unwrap();
+        let mut rdr = csv::Reader::from_reader(output.as_slice());
+        let mut clients: Vec<(u16, Decimal, Decimal, Decimal)> = rdr
+            .deserialize::<(u16, Decimal, Decimal, Decimal, bool)>()
+            .map(|row| {
+                let (id, available, held, total, _) = row.unwrap();
+                (id, available, held, total)
+            })
+            .collect();
+        clients.sort();
+
+        assert_eq!(
+            clients,
+            vec![
+                (1, dec!(7.5), dec!(0), dec!(7.5)),
+                (2, dec!(5), dec!(0), dec!(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_from_a_client_other_than_the_one_that_disputed_is_rejected() {
+        let mut store = MemoryStore::default();
+        let mut client = Client::new(1);
+        client.deposit(dec!(10));
+        client.hold(dec!(10));
+        store.clients.insert(1, client);
+        store.clients.insert(2, Client::new(2));
+        store.transactions.insert(
+            1,
+            Transaction {
+                disputed: true,
+                disputed_by: Some(1),
+                ..Transaction::deposit(1, 1, dec!(10))
+            },
+        );
+
+        assert_eq!(
+            handle_transaction(Transaction::resolve(2, 1), &mut store, &Options::default(),),
+            Err(TransactionError::ClientMismatch {
+                tx: 1,
+                client: 2,
+                owner: 1
+            })
+        );
+        assert_eq!(store.clients.get(&1).unwrap().held_amount, dec!(10));
+        assert_eq!(store.clients.get(&2).unwrap(), &Client::new(2));
+
+        handle_transaction(Transaction::resolve(1, 1), &mut store, &Options::default()).unwrap();
+        assert_eq!(store.clients.get(&1).unwrap().held_amount, dec!(0));
+        assert_eq!(store.clients.get(&1).unwrap().available_amount, dec!(10));
+    }
+
+    #[test]
+    fn deposit_starts_pending_and_settles_on_a_settle_row() {
+        let client_id = 1;
+        let mut store = MemoryStore::default();
+        let options = Options {
+            pending: true,
+            ..Options::default()
+        };
+
+        let deposit = Transaction::deposit(client_id, 1, dec!(12.5));
+        handle_transaction(deposit, &mut store, &options).unwrap();
+
+        let client = store.clients.get(&client_id).unwrap();
+        assert_eq!(client.available_amount, dec!(0));
+        assert_eq!(client.pending_amount, Some(dec!(12.5)));
+        assert_eq!(client.total_amount, dec!(12.5));
+
+        let settle = Transaction::new(TransactionType::Settle, client_id, 2, None);
+        handle_transaction(settle, &mut store, &options).unwrap();
+
+        let client = store.clients.get(&client_id).unwrap();
+        assert_eq!(client.available_amount, dec!(12.5));
+        assert_eq!(client.pending_amount, Some(dec!(0)));
+        assert_eq!(client.total_amount, dec!(12.5));
+        assert!(!store.transactions.contains_key(&2));
+    }
+
+    #[test]
+    fn balances_netting_to_zero_are_written_as_positive_zero() {
+        let mut client_list = ClientList::default();
+        let mut netted = Client::new(1);
+        netted.deposit(dec!(2.5));
+        netted.withdraw(dec!(2.5));
+        client_list.insert(1, netted);
+
+        let negative_zero = Client::builder()
+            .id(2)
+            .available(-dec!(0.0000))
+            .total(-dec!(0.0000))
+            .build();
+        assert!(negative_zero.available_amount.is_sign_negative());
+        client_list.insert(2, negative_zero);
+
+        let mut output = Vec::new();
+        write_clients(client_list.values(), &OutputOptions::default(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(!output.contains("-0"));
+        assert!(output.contains("1,0.0000,0.0000,0.0000,false\n"));
+        assert!(output.contains("2,0.0000,0.0000,0.0000,false\n"));
+    }
+
+    #[test]
+    fn balances_are_written_rounded_to_four_places() {
+        let (client_list, _) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(1.00005)),
+                Transaction::deposit(1, 2, dec!(1.00006)),
+            ],
+            &Options::default(),
+        );
+        // the balance itself keeps every place
+        assert_eq!(client_list.get(&1).unwrap().total_amount, dec!(2.00011));
+
+        let mut output = Vec::new();
+        write_clients(client_list.values(), &OutputOptions::default(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,2.0001,0.0000,2.0001,false\n"
+        );
+    }
+
+    #[test]
+    fn columns_selects_which_client_columns_are_written() {
+        let mut client_list = ClientList::default();
+        let mut client = Client::new(1);
+        client.deposit(dec!(3));
+        client.hold(dec!(1));
+        client_list.insert(1, client);
+
+        let mut output = Vec::new();
+        let options = OutputOptions {
+            columns: Some(vec![Column::Available, Column::Total]),
+            ..OutputOptions::default()
+        };
+        write_clients(client_list.values(), &options, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(output, "client,available,total\n1,2.0000,3.0000\n");
+        assert!(!output.contains("held"));
+    }
+
+    #[test]
+    fn with_holds_only_writes_clients_holding_funds() {
+        let mut client_list = ClientList::default();
+        let mut holder = Client::new(1);
+        holder.deposit(dec!(3));
+        holder.hold(dec!(1));
+        client_list.insert(1, holder);
+        let mut other = Client::new(2);
+        other.deposit(dec!(5));
+        client_list.insert(2, other);
+
+        let mut output = Vec::new();
+        let options = OutputOptions {
+            with_holds: true,
+            ..OutputOptions::default()
+        };
+        write_clients(client_list.values(), &options, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,2.0000,1.0000,3.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn client_with_a_total_that_doesnt_add_up_is_inconsistent() {
+        let mut client = Client::new(1);
+        client.deposit(dec!(10));
+        client.hold(dec!(4));
+        client.deposit_pending(dec!(1));
+        assert!(client.is_consistent());
+
+        client.total_amount -= dec!(4);
+        assert!(!client.is_consistent());
+    }
+
+    #[test]
+    fn disputing_a_deposit_holds_it_but_a_withdrawal_is_rejected() {
+        let mut engine = PaymentsEngine::default();
+        engine
+            .process(Transaction::deposit(1, 1, dec!(10)))
+            .unwrap();
+        engine
+            .process(Transaction::withdrawal(1, 2, dec!(3)))
+            .unwrap();
+
+        assert_eq!(
+            engine.apply_all([Transaction::dispute(1, 2)], ErrorMode::Stop),
+            Err(TransactionError::UnknownTransaction { tx: 2 })
+        );
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available_amount, dec!(7));
+        assert_eq!(client.held_amount, dec!(0));
+
+        engine.process(Transaction::dispute(1, 1)).unwrap();
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available_amount, dec!(-3));
+        assert_eq!(client.held_amount, dec!(10));
+        assert_eq!(client.total_amount, dec!(7));
+    }
+
+    #[test]
+    fn a_withdrawal_in_the_transaction_list_still_cant_be_disputed() {
+        let mut store = MemoryStore::default();
+        store
+            .transactions
+            .insert(1, Transaction::withdrawal(1, 1, dec!(3)));
+        store.clients.insert(1, Client::new(1));
+
+        assert_eq!(
+            handle_transaction(Transaction::dispute(1, 1), &mut store, &Options::default(),),
+            Err(TransactionError::NotADeposit { tx: 1 })
+        );
+        assert_eq!(store.clients[&1].held_amount, dec!(0));
+    }
+
+    #[test]
+    fn many_clients_are_written_with_regular_flushes() {
+        // counts the flushes that reach the underlying writer
+        #[derive(Default)]
+        struct Flushes {
+            written: Vec<u8>,
+            flushes: usize,
+        }
+        impl io::Write for Flushes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.written.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushes += 1;
+                Ok(())
+            }
+        }
+
+        let clients: Vec<Client> = (1..=10_000).map(Client::new).collect();
+        let mut output = Flushes::default();
+        write_clients(&clients, &OutputOptions::default(), &mut output).unwrap();
+
+        let rows = String::from_utf8(output.written).unwrap();
+        assert_eq!(rows.lines().count(), 10_001);
+        assert!(output.flushes > 10_000 / FLUSH_EVERY);
+    }
+
+    #[test]
+    fn precision_rounds_half_to_even() {
+        let written = |precision: u32, amount: Decimal| {
+            let mut client = Client::new(1);
+            client.deposit(amount);
+            let options = OutputOptions {
+                precision: Some(precision),
+                columns: Some(vec![Column::Available]),
+                skip_header: true,
+                ..OutputOptions::default()
+            };
+            let mut output = Vec::new();
+            write_clients([&client], &options, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(written(2, dec!(1.005)), "1,1.00\n");
+        assert_eq!(written(2, dec!(1.015)), "1,1.02\n");
+        assert_eq!(written(2, dec!(3.14159)), "1,3.14\n");
+        assert_eq!(written(0, dec!(2.5)), "1,2\n");
+        assert_eq!(written(0, dec!(3.5)), "1,4\n");
+        assert_eq!(written(0, dec!(0.4)), "1,0\n");
+    }
+
+    #[test]
+    fn drifted_total_is_written_recomputed() {
+        let drifted = Client::builder()
+            .id(1)
+            .available(dec!(7))
+            .held(dec!(3))
+            .total(dec!(13))
+            .build();
+        assert!(!drifted.is_consistent());
+        assert_eq!(drifted.total_recomputed(), dec!(10));
+
+        let written = |recompute_totals| {
+            let output = OutputOptions {
+                recompute_totals,
+                ..OutputOptions::default()
+            };
+            let mut csv = Vec::new();
+            write_clients([&drifted], &output, &mut csv).unwrap();
+            String::from_utf8(csv).unwrap()
+        };
+        assert!(written(false).ends_with("1,7.0000,3.0000,13.0000,false\n"));
+        assert!(written(true).ends_with("1,7.0000,3.0000,10.0000,false\n"));
+    }
+
+    #[test]
+    fn client_displays_its_balances() {
+        let mut client = Client::new(1);
+        client.deposit(dec!(15));
+        client.hold(dec!(5));
+        assert_eq!(
+            client.to_string(),
+            "client 1: available=10.0000 held=5.0000 total=15.0000 locked=false"
+        );
+
+        let mut pending = Client::with_pending(2);
+        pending.deposit_pending(dec!(1.23456));
+        pending.freeze();
+        assert_eq!(
+            pending.to_string(),
+            "client 2: available=0.0000 held=0.0000 total=1.2346 locked=true pending=1.2346"
+        );
+    }
+
+    // everything `run` logs up to `level`, as the binary would write it to stderr
+    fn logged(level: tracing::Level, run: impl FnOnce()) -> String {
+        #[derive(Clone, Default)]
+        struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl io::Write for Captured {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, run);
+        let logged = captured.0.lock().unwrap().clone();
+        String::from_utf8(logged).unwrap()
+    }
+
+    #[test]
+    fn rejected_withdrawal_is_logged_as_a_warning() {
+        let logged = logged(tracing::Level::WARN, || {
+            let mut engine = PaymentsEngine::default();
+            engine.process(Transaction::deposit(1, 1, dec!(1))).unwrap();
+            engine
+                .process(Transaction::withdrawal(1, 2, dec!(5)))
+                .unwrap();
+        });
+        assert_eq!(logged.lines().count(), 1);
+        assert!(logged.contains("WARN"));
+        assert!(logged.contains("skipped Withdrawal"));
+        assert!(logged.contains("tx=2"));
+        assert!(logged.contains("doesn't have the available funds"));
+    }
+
+    #[test]
+    fn clients_are_written_in_ascending_id_order() {
+        let mut client_list = ClientList::default();
+        for id in [40, 3, 17, 250, 1, 9, 1000, 2] {
+            client_list.insert(id, Client::new(id));
+        }
+
+        let mut output = Vec::new();
+        write_clients(client_list.values(), &OutputOptions::default(), &mut output).unwrap();
+
+        let ids: Vec<u16> = ReaderBuilder::new()
+            .from_reader(output.as_slice())
+            .deserialize()
+            .map(|client: csv::Result<Client>| client.unwrap().id)
+            .collect();
+        assert_eq!(ids.len(), 8);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn dispute_left_open_past_auto_resolve_after_releases_its_funds() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+dispute,1,1,
+deposit,2,2,1.0
+deposit,2,3,1.0
+";
+        let run = |auto_resolve_after| {
+            let mut engine = PaymentsEngine::new(Options {
+                auto_resolve_after: Some(auto_resolve_after),
+                ..Options::default()
+            });
+            process_reader(input.as_bytes(), &mut engine).unwrap();
+            engine.client(1).unwrap().clone()
+        };
+
+        let released = run(2);
+        assert_eq!(released.held_amount, dec!(0));
+        assert_eq!(released.available_amount, dec!(10));
+        assert_eq!(released.total_amount, dec!(10));
+
+        let still_held = run(3);
+        assert_eq!(still_held.held_amount, dec!(10));
+        assert_eq!(still_held.available_amount, dec!(0));
+    }
+
+    #[test]
+    fn auto_resolving_the_dispute_of_a_closed_client_is_skipped() {
+        // this used to panic looking for the client the dispute had to release funds for
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+dispute,1,1,
+closeclient,1,2,
+deposit,2,3,1.0
+deposit,2,4,1.0
+";
+        let mut engine = PaymentsEngine::new(Options {
+            auto_resolve_after: Some(2),
+            ..Options::default()
+        });
+        process_reader(input.as_bytes(), &mut engine).unwrap();
+        assert!(engine.client(1).is_none());
+        assert_eq!(engine.client(2).unwrap().available_amount, dec!(2));
+
+        assert_eq!(
+            auto_resolve(9, &mut MemoryStore::default(), &Options::default()),
+            Err(TransactionError::UnknownTransaction { tx: 9 })
+        );
+    }
+
+    #[test]
+    fn closed_client_is_written_early_and_left_out_of_the_final_output() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+closeclient,1,3,
+deposit,2,4,1.0
+";
+        let mut engine = PaymentsEngine::default();
+        let mut early = Vec::new();
+        process_transactions(
+            input.as_bytes(),
+            &mut engine,
+            || false,
+            |transaction, outcome, engine| {
+                if transaction.transaction_type == TransactionType::CloseClient {
+                    assert_eq!(outcome, Outcome::Applied);
+                    let client = engine.client(transaction.client_id);
+                    write_clients(client, &OutputOptions::default(), &mut early)?;
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(early).unwrap(),
+            "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+        );
+        let ids: Vec<u16> = engine.clients().map(|client| client.id).collect();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn rows_after_a_close_dont_bring_the_client_back() {
+        let transactions = vec![
+            Transaction::deposit(1, 1, dec!(10)),
+            Transaction::new(TransactionType::CloseClient, 1, 2, None),
+            Transaction::deposit(1, 3, dec!(5)),
+            Transaction::dispute(1, 1),
+            Transaction::deposit(2, 4, dec!(5)),
+            Transaction::transfer(2, 5, 1, dec!(1)),
+        ];
+        let mut engine = PaymentsEngine::default();
+        let skipped = engine
+            .apply_all(transactions.clone(), ErrorMode::Collect)
+            .unwrap();
+        assert_eq!(
+            skipped,
+            vec![TransactionError::ClientClosed { client: 1 }; 3]
+        );
+        let ids: Vec<u16> = engine.clients().map(|client| client.id).collect();
+        assert_eq!(ids, vec![2]);
+
+        let mut engine = PaymentsEngine::default();
+        let rows = transactions.into_iter().map(Ok);
+        apply_transactions(rows, &mut engine, || false, |_, _, _| Ok(())).unwrap();
+        let ids: Vec<u16> = engine.clients().map(|client| client.id).collect();
+        assert_eq!(ids, vec![2]);
+        assert_eq!(engine.stats().ignored["client closed"], 3);
+    }
+
+    #[test]
+    fn closing_through_apply_drops_the_client_too() {
+        let mut engine = PaymentsEngine::default();
+        engine.apply(Transaction::deposit(1, 1, dec!(10))).unwrap();
+        assert_eq!(
+            engine.apply(Transaction::new(TransactionType::CloseClient, 1, 2, None)),
+            Ok(Outcome::Applied)
+        );
+        // still there to report on until the next row
+        assert_eq!(engine.client(1).unwrap().available_amount, dec!(10));
+        assert_eq!(engine.clients().count(), 0);
+
+        assert_eq!(
+            engine.apply(Transaction::deposit(1, 3, dec!(5))),
+            Ok(Outcome::Skipped)
+        );
+        assert!(engine.client(1).is_none());
+    }
+
+    #[test]
+    fn trace_shows_the_balances_before_and_after_a_deposit() {
+        let logged = logged(tracing::Level::INFO, || {
+            let mut store = MemoryStore::default();
+            let options = Options {
+                trace: true,
+                ..Options::default()
+            };
+            handle_transaction(Transaction::deposit(1, 1, dec!(2.5)), &mut store, &options)
+                .unwrap();
+            handle_transaction(Transaction::deposit(1, 2, dec!(1.5)), &mut store, &options)
+                .unwrap();
+        });
+        let lines: Vec<&str> = logged.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains(" INFO trace: "), "{}", lines[1]);
+        assert!(lines[1].ends_with(
+            "Deposit tx 2 client 1: available=2.5 held=0 total=2.5 locked=false -> available=4.0 held=0 total=4.0 locked=false"
+        ));
+    }
+
+    #[test]
+    fn strict_invariants_stops_on_balances_that_dont_add_up() {
+        // no transaction leaves the balances like this, it's built that way to stand in for
+        // a bug that did
+        let run = |strict_invariants| {
+            let mut store = MemoryStore::default();
+            let drifted = Client::builder()
+                .id(1)
+                .available(dec!(7))
+                .held(dec!(3))
+                .total(dec!(13))
+                .build();
+            store.clients.insert(1, drifted);
+            let options = Options {
+                strict_invariants,
+                ..Options::default()
+            };
+            handle_transaction(Transaction::deposit(1, 1, dec!(1)), &mut store, &options)
+        };
+
+        assert!(run(false).is_ok());
+        let error = run(true).unwrap_err().to_string();
+        assert!(error.starts_with(
+            "Invariant violated for client 1: available 8 + held 3 + pending 0 != total 14"
+        ));
+        assert!(error.contains("Deposit"));
+    }
+
+    #[test]
+    fn process_reader_reads_a_csv_straight_from_memory() {
+        let input: &[u8] = b"\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+withdrawl,1,3,2.5
+dispute,2,2,
+";
+        let mut engine = PaymentsEngine::default();
+        process_reader(input, &mut engine).unwrap();
+
+        let first = engine.client(1).unwrap();
+        assert_eq!(first.available_amount, dec!(7.5));
+        assert_eq!(first.total_amount, dec!(7.5));
+        let second = engine.client(2).unwrap();
+        assert_eq!(second.available_amount, dec!(0));
+        assert_eq!(second.held_amount, dec!(5));
+        assert_eq!(second.total_amount, dec!(5));
+    }
+
+    #[test]
+    fn json_lines_give_the_same_balances_as_the_csv() {
+        let run = |file: &str, format| {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("priv")
+                .join(file);
+            let mut engine = PaymentsEngine::default();
+            let transactions = read_transactions(File::open(path).unwrap(), format);
+            apply_transactions(transactions, &mut engine, || false, |_, _, _| Ok(())).unwrap();
+            let mut clients: Vec<Client> = engine.clients().cloned().collect();
+            clients.sort_by_key(|client| client.id);
+            clients
+        };
+
+        let from_json = run("small_sample.jsonl", InputFormat::Json);
+        assert_eq!(from_json, run("small_sample.csv", InputFormat::Csv));
+        assert_eq!(from_json[0].held_amount, dec!(1));
+        assert_eq!(from_json[0].available_amount, dec!(0.5));
+    }
+
+    #[test]
+    fn header_with_the_expected_columns_is_read() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\n";
+        let transactions: Vec<Transaction> = read_transactions(input.as_bytes(), InputFormat::Csv)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(transactions, vec![Transaction::deposit(1, 1, dec!(5.0))]);
+    }
+
+    #[test]
+    fn misnamed_column_fails_before_any_row() {
+        let input = "type,client,transaction,amount\ndeposit,1,1,5.0\ndeposit,1,2,5.0\n";
+        let mut errors = Vec::new();
+        let results: Vec<Result<Transaction>> = skip_invalid_rows(
+            read_transactions(input.as_bytes(), InputFormat::Csv),
+            &mut errors,
+        )
+        .collect();
+
+        // not one of the rows it would otherwise make every row skip
+        assert!(errors.is_empty());
+        assert_eq!(results.len(), 1);
+        let error = results.into_iter().next().unwrap().unwrap_err();
+        assert_eq!(error.to_string(), "missing required column: tx");
+    }
+
+    #[test]
+    fn columns_in_any_order_give_the_same_balances() {
+        let balances = |input: &str| {
+            let mut engine = PaymentsEngine::default();
+            process_reader(input.as_bytes(), &mut engine).unwrap();
+            let mut clients: Vec<Client> = engine.clients().cloned().collect();
+            clients.sort_by_key(|client| client.id);
+            clients
+        };
+        let canonical = balances(
+            "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,3.5
+withdrawal,1,3,2.25
+dispute,2,2,
+",
+        );
+        assert_eq!(canonical.len(), 2);
+
+        let reordered = [
+            "client,type,tx,amount
+1,deposit,1,10.0
+2,deposit,2,3.5
+1,withdrawal,3,2.25
+2,dispute,2,
+",
+            "amount,tx,client,type
+10.0,1,1,deposit
+3.5,2,2,deposit
+2.25,3,1,withdrawal
+,2,2,dispute
+",
+        ];
+        for input in reordered {
+            assert_eq!(balances(input), canonical, "{}", input);
+        }
+    }
+
+    #[test]
+    fn extra_columns_in_the_header_are_ignored() {
+        let input = "type,note,client,tx,amount,source\ndeposit,first,1,1,5.0,bank\n";
+        let transactions: Vec<Transaction> = read_transactions(input.as_bytes(), InputFormat::Csv)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(transactions, vec![Transaction::deposit(1, 1, dec!(5.0))]);
+    }
+
+    #[test]
+    fn tab_separated_input_gives_the_same_balances_as_the_csv() {
+        let run = |file: &str, delimiter| {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("priv")
+                .join(file);
+            let mut engine = PaymentsEngine::default();
+            let transactions = read_transactions_with_delimiter(
+                File::open(path).unwrap(),
+                InputFormat::Csv,
+                delimiter,
+            );
+            apply_transactions(transactions, &mut engine, || false, |_, _, _| Ok(())).unwrap();
+            engine
+        };
+
+        let from_tsv = run("small_sample.tsv", b'\t');
+        let from_csv = run("small_sample.csv", b',');
+        let mut clients: Vec<&Client> = from_tsv.clients().collect();
+        let mut expected: Vec<&Client> = from_csv.clients().collect();
+        clients.sort_by_key(|client| client.id);
+        expected.sort_by_key(|client| client.id);
+        assert_eq!(clients, expected);
+
+        // and written back out with tabs too
+        let mut output = Vec::new();
+        let options = OutputOptions {
+            delimiter: Some(b'\t'),
+            ..OutputOptions::default()
+        };
+        write_clients(clients, &options, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client\tavailable\theld\ttotal\tlocked\n1\t0.5000\t1.0000\t1.5000\tfalse\n2\t2.0000\t0.0000\t2.0000\tfalse\n"
+        );
+    }
+
+    #[test]
+    fn json_output_describes_the_same_balances_as_csv() {
+        let mut engine = PaymentsEngine::default();
+        process_reader(
+            File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("priv/small_sample.csv"))
+                .unwrap(),
+            &mut engine,
+        )
+        .unwrap();
+
+        let written = |format| {
+            let mut output = Vec::new();
+            let options = OutputOptions {
+                format,
+                ..OutputOptions::default()
+            };
+            write_clients(engine.clients(), &options, &mut output).unwrap();
+            output
+        };
+
+        let mut from_csv: Vec<Client> =
+            csv::Reader::from_reader(written(OutputFormat::Csv).as_slice())
+                .deserialize()
+                .map(|client| client.unwrap())
+                .collect();
+        let mut from_json: Vec<Client> =
+            serde_json::from_slice(&written(OutputFormat::Json)).unwrap();
+        from_csv.sort_by_key(|client| client.id);
+        from_json.sort_by_key(|client| client.id);
+        assert_eq!(from_json, from_csv);
+        assert_eq!(from_json.len(), 2);
+    }
+
+    #[test]
+    fn only_deposits_are_kept_for_disputes() {
+        let mut engine = PaymentsEngine::default();
+        engine
+            .process(Transaction::deposit(1, 1, dec!(1000000)))
+            .unwrap();
+        for id in 2..100_002 {
+            engine
+                .process(Transaction::withdrawal(1, id, dec!(0.0001)))
+                .unwrap();
+        }
+
+        // a hundred thousand withdrawals used to mean a hundred thousand stored transactions
+        assert_eq!(engine.memory.transactions.len(), 1);
+        assert_eq!(engine.client(1).unwrap().available_amount, dec!(999990));
+
+        // disputing a withdrawal finds nothing to hold
+        assert_eq!(
+            engine.apply(Transaction::dispute(1, 2)).unwrap(),
+            Outcome::Skipped
+        );
+    }
+
+    #[test]
+    fn a_garbage_row_is_skipped_and_the_rows_around_it_still_apply() {
+        let input =
+            File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("priv/garbage_row.csv")).unwrap();
+        let mut errors = Vec::new();
+        let mut engine = PaymentsEngine::default();
+        apply_transactions(
+            skip_invalid_rows(read_transactions(input, InputFormat::Csv), &mut errors),
+            &mut engine,
+            || false,
+            |_, _, _| Ok(()),
+        )
+        .unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].row, 2);
+        assert_eq!(engine.client(1).unwrap().available_amount, dec!(12));
+        assert_eq!(engine.client(1).unwrap().held_amount, dec!(10));
+        assert_eq!(engine.client(2).unwrap().available_amount, dec!(5));
+
+        // the same row in json lines
+        let input = "\
+{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1.5}
+{\"type\":\"deposit\",\"client\":1,
+{\"type\":\"deposit\",\"client\":1,\"tx\":3,\"amount\":2}
+";
+        let mut errors = Vec::new();
+        let mut engine = PaymentsEngine::default();
+        apply_transactions(
+            skip_invalid_rows(
+                read_transactions(input.as_bytes(), InputFormat::Json),
+                &mut errors,
+            ),
+            &mut engine,
+            || false,
+            |_, _, _| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].row, 2);
+        assert_eq!(engine.client(1).unwrap().available_amount, dec!(3.5));
+    }
+
+    #[test]
+    fn a_reused_deposit_id_keeps_the_first_deposit() {
+        let mut engine = PaymentsEngine::default();
+        engine
+            .process(Transaction::deposit(1, 1, dec!(10)))
+            .unwrap();
+        assert_eq!(
+            engine.apply_all([Transaction::deposit(1, 1, dec!(99))], ErrorMode::Stop),
+            Err(TransactionError::DuplicateTransaction { tx: 1 })
+        );
+        assert_eq!(engine.transaction(1).unwrap().amount, Some(dec!(10)));
+        assert_eq!(engine.client(1).unwrap().available_amount, dec!(10));
+
+        engine.process(Transaction::dispute(1, 1)).unwrap();
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.held_amount, dec!(10));
+        assert_eq!(client.available_amount, dec!(0));
+
+        let mut strict = PaymentsEngine::new(Options {
+            strict: true,
+            ..Options::default()
+        });
+        strict.apply(Transaction::deposit(1, 1, dec!(10))).unwrap();
+        assert!(strict.apply(Transaction::deposit(1, 1, dec!(99))).is_err());
+    }
+
+    #[test]
+    fn extra_trailing_columns_are_ignored() {
+        let input = "\
+type,client,tx,amount,note
+deposit,1,1,10.0,first deposit
+deposit,1,2,5.0,
+withdrawl,1,3,2.5,
+deposit,1,4,1.0,late,with,even,more,columns
+";
+        let mut engine = PaymentsEngine::default();
+        process_reader(input.as_bytes(), &mut engine).unwrap();
+
+        assert!(engine.transaction(4).is_some());
+        assert_eq!(engine.transaction(1).unwrap().amount, Some(dec!(10.0)));
+        assert_eq!(engine.client(1).unwrap().available_amount, dec!(13.5));
+    }
+
+    #[test]
+    fn currency_scale_sets_the_places_balances_are_written_with() {
+        let mut client_list = ClientList::default();
+        let mut client = Client::new(1);
+        client.deposit(dec!(1234.4));
+        client.hold(dec!(10));
+        client_list.insert(1, client);
+
+        let written = |code| {
+            let mut output = Vec::new();
+            let options = OutputOptions {
+                scale: Some(currency::scale(code).unwrap()),
+                ..OutputOptions::default()
+            };
+            write_clients(client_list.values(), &options, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            written("JPY"),
+            "client,available,held,total,locked\n1,1224,10,1234,false\n"
+        );
+        assert_eq!(
+            written("USD"),
+            "client,available,held,total,locked\n1,1224.40,10.00,1234.40,false\n"
+        );
+    }
+
+    #[test]
+    fn written_total_adds_up_the_rounded_balances() {
+        let written = |amount, scale| {
+            let mut engine = PaymentsEngine::default();
+            engine.process(Transaction::deposit(1, 1, amount)).unwrap();
+            engine.process(Transaction::deposit(1, 2, amount)).unwrap();
+            engine.process(Transaction::dispute(1, 1)).unwrap();
+            let options = OutputOptions {
+                scale,
+                ..OutputOptions::default()
+            };
+            let mut output = Vec::new();
+            write_clients(engine.clients(), &options, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // each half rounds down to even on its own, where the unrounded total of 0.0001
+        // wouldn't
+        assert_eq!(
+            written(dec!(0.00005), None),
+            "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n"
+        );
+        assert_eq!(
+            written(dec!(0.005), Some(currency::scale("USD").unwrap())),
+            "client,available,held,total,locked\n1,0.00,0.00,0.00,false\n"
+        );
+    }
+
+    #[test]
+    fn transaction_can_be_built_from_a_string_record() {
+        let record = csv::StringRecord::from(vec!["deposit", " 2", "7 ", "1.5", "eu"]);
+        let mut expected = Transaction::deposit(2, 7, dec!(1.5));
+        expected.region = Some("eu".to_string());
+        assert_eq!(Transaction::try_from(&record).unwrap(), expected);
+
+        let record = csv::StringRecord::from(vec!["dispute", "2", "7", ""]);
+        assert_eq!(
+            Transaction::try_from(&record).unwrap(),
+            Transaction::dispute(2, 7)
+        );
+
+        let record = csv::StringRecord::from(vec!["deposit", "2", "7", "1.5.0"]);
+        let error = Transaction::try_from(&record).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid amount field"));
+    }
+
+    #[test]
+    fn apply_all_deposit_dispute_resolve_in_one_call() {
+        let mut engine = PaymentsEngine::default();
+        let skipped = engine
+            .apply_all(
+                vec![
+                    Transaction::deposit(1, 1, dec!(10)),
+                    Transaction::dispute(1, 1),
+                    Transaction::resolve(1, 1),
+                ],
+                ErrorMode::Stop,
+            )
+            .unwrap();
+
+        assert!(skipped.is_empty());
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available_amount, dec!(10));
+        assert_eq!(client.held_amount, dec!(0));
+        assert_eq!(client.total_amount, dec!(10));
+        assert!(!engine.is_disputed(1));
+    }
+
+    #[test]
+    fn apply_all_stops_at_or_collects_the_skipped_rows() {
+        let transactions = vec![
+            Transaction::deposit(1, 1, dec!(10)),
+            Transaction::withdrawal(1, 2, dec!(50)),
+            Transaction::resolve(1, 1),
+            Transaction::deposit(1, 3, dec!(5)),
+        ];
+
+        let mut engine = PaymentsEngine::default();
+        assert_eq!(
+            engine.apply_all(transactions.clone(), ErrorMode::Stop),
+            Err(TransactionError::InsufficientFunds { client: 1, tx: 2 })
+        );
+        assert_eq!(engine.client(1).unwrap().total_amount, dec!(10));
+
+        let mut engine = PaymentsEngine::default();
+        assert_eq!(
+            engine.apply_all(transactions, ErrorMode::Collect),
+            Ok(vec![
+                TransactionError::InsufficientFunds { client: 1, tx: 2 },
+                TransactionError::NotDisputed { tx: 1 },
+            ])
+        );
+        assert_eq!(engine.client(1).unwrap().total_amount, dec!(15));
+    }
+
+    #[test]
+    fn restored_snapshots_carry_on_independently() {
+        let mut engine = PaymentsEngine::default();
+        engine
+            .apply_all(
+                vec![
+                    Transaction::deposit(1, 1, dec!(10)),
+                    Transaction::deposit(2, 2, dec!(5)),
+                    Transaction::dispute(1, 1),
+                ],
+                ErrorMode::Stop,
+            )
+            .unwrap();
+        let checkpoint = engine.snapshot();
+
+        let mut resolved = PaymentsEngine::restore(checkpoint.clone());
+        resolved.process(Transaction::resolve(1, 1)).unwrap();
+        let mut charged_back = PaymentsEngine::restore(checkpoint.clone());
+        charged_back.process(Transaction::chargeback(1, 1)).unwrap();
+        charged_back
+            .process(Transaction::withdrawal(2, 3, dec!(5)))
+            .unwrap();
+
+        let client = resolved.client(1).unwrap();
+        assert_eq!(client.available_amount, dec!(10));
+        assert_eq!(client.total_amount, dec!(10));
+        assert!(!client.locked);
+        assert_eq!(resolved.client(2).unwrap().total_amount, dec!(5));
+
+        let client = charged_back.client(1).unwrap();
+        assert_eq!(client.total_amount, dec!(0));
+        assert!(client.locked);
+        assert_eq!(charged_back.client(2).unwrap().total_amount, dec!(0));
+
+        // neither continuation touched the checkpoint or the engine it was taken from
+        assert_eq!(engine.snapshot(), checkpoint);
+        assert_eq!(engine.client(1).unwrap().held_amount, dec!(10));
+    }
+
+    #[test]
+    fn dispute_of_a_missing_tx_is_skipped_without_creating_the_client() {
+        let mut engine = PaymentsEngine::default();
+        engine
+            .process(Transaction::deposit(1, 1, dec!(10)))
+            .unwrap();
+        for transaction in [
+            Transaction::dispute(2, 99),
+            Transaction::resolve(2, 99),
+            Transaction::chargeback(1, 99),
+        ] {
+            assert_eq!(engine.apply(transaction), Ok(Outcome::Skipped));
+        }
+        assert!(engine.client(2).is_none());
+        assert_eq!(engine.client(1).unwrap().available_amount, dec!(10));
+        assert_eq!(
+            engine.apply_all([Transaction::dispute(2, 99)], ErrorMode::Stop),
+            Err(TransactionError::UnknownTransaction { tx: 99 })
+        );
+    }
+
+    #[test]
+    fn dispute_alone_writes_no_clients() {
+        let input = "\
+type,client,tx,amount
+dispute,7,1,
+";
+        let mut engine = PaymentsEngine::default();
+        process_reader(input.as_bytes(), &mut engine).unwrap();
+        let mut output = Vec::new();
+        write_clients(engine.clients(), &OutputOptions::default(), &mut output).unwrap();
+        // the header is only written along with the first client
+        assert_eq!(String::from_utf8(output).unwrap(), "");
+    }
+
+    #[test]
+    fn dispute_of_another_clients_tx_doesnt_create_the_disputer() {
+        let mut engine = PaymentsEngine::default();
+        engine
+            .process(Transaction::deposit(1, 1, dec!(10)))
+            .unwrap();
+        assert_eq!(
+            engine.apply_all([Transaction::dispute(2, 1)], ErrorMode::Stop),
+            Err(TransactionError::ClientMismatch {
+                tx: 1,
+                client: 2,
+                owner: 1
+            })
+        );
+        assert!(engine.client(2).is_none());
+        assert!(!engine.is_disputed(1));
+
+        // a row that's ignored for any other reason still adds its client
+        assert_eq!(
+            engine.process(Transaction::withdrawal(3, 2, dec!(1))),
+            Ok(vec![])
+        );
+        assert_eq!(engine.client(3).unwrap().total_amount, dec!(0));
+    }
+
+    #[test]
+    fn dispute_of_a_missing_tx_stops_a_strict_run() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+dispute,2,99,
+deposit,1,2,5.0
+";
+        let mut engine = PaymentsEngine::new(Options {
+            strict: true,
+            ..Options::default()
+        });
+        let error = process_reader(input.as_bytes(), &mut engine).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::UnknownTransaction { tx: 99 })
+        );
+        assert!(engine.client(2).is_none());
+        assert_eq!(engine.client(1).unwrap().available_amount, dec!(10));
+    }
+
+    #[test]
+    fn rounding_each_transaction_differs_from_rounding_the_output() {
+        let deposits = || (1..=3).map(|tx| Transaction::deposit(1, tx, dec!(0.00005)));
+        let written = |round_each| {
+            let mut engine = PaymentsEngine::new(Options {
+                round_each,
+                ..Options::default()
+            });
+            for transaction in deposits() {
+                engine.process(transaction).unwrap();
+            }
+            let client = engine.client(1).unwrap().clone();
+            let mut output = Vec::new();
+            write_clients([&client], &OutputOptions::default(), &mut output).unwrap();
+            (client, String::from_utf8(output).unwrap())
+        };
+
+        // full precision until it's written, then 0.00015 rounds half to even
+        let (client, output) = written(RoundEach::None);
+        assert_eq!(client.total_amount, dec!(0.00015));
+        assert!(
+            output.ends_with("\n1,0.0002,0.0000,0.0002,false\n"),
+            "{}",
+            output
+        );
+
+        // 0.0001, 0.0002, 0.0003
+        let (client, output) = written(RoundEach::HalfUp);
+        assert_eq!(client.available_amount, dec!(0.0003));
+        assert_eq!(client.total_amount, dec!(0.0003));
+        assert!(
+            output.ends_with("\n1,0.0003,0.0000,0.0003,false\n"),
+            "{}",
+            output
+        );
+
+        // every 0.00005 rounds back down to zero
+        let (client, output) = written(RoundEach::HalfEven);
+        assert_eq!(client.total_amount, dec!(0));
+        assert!(
+            output.ends_with("\n1,0.0000,0.0000,0.0000,false\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn round_each_rounds_to_the_given_precision_and_keeps_the_total_consistent() {
+        let mut engine = PaymentsEngine::new(Options {
+            round_each: RoundEach::HalfUp,
+            round_precision: Some(2),
+            ..Options::default()
+        });
+        engine
+            .process(Transaction::deposit(1, 1, dec!(1.005)))
+            .unwrap();
+        engine
+            .process(Transaction::deposit(1, 2, dec!(0.005)))
+            .unwrap();
+        assert_eq!(engine.client(1).unwrap().total_amount, dec!(1.02));
+
+        // 1.015 available and 0.005 held, each rounded up on their own
+        engine.process(Transaction::dispute(1, 2)).unwrap();
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available_amount, dec!(1.02));
+        assert_eq!(client.held_amount, dec!(0.01));
+        assert_eq!(client.total_amount, dec!(1.03));
+        assert!(client.is_consistent());
+    }
+
+    #[test]
+    fn process_answers_with_the_clients_it_changed() {
+        let mut engine = PaymentsEngine::default();
+        assert_eq!(
+            engine.process(Transaction::deposit(1, 1, dec!(10))),
+            Ok(vec![1])
+        );
+        // ignored, so nothing to refresh
+        assert_eq!(engine.process(Transaction::dispute(1, 99)), Ok(vec![]));
+        assert_eq!(
+            engine.process(Transaction::transfer(1, 2, 3, dec!(4))),
+            Ok(vec![1, 3])
+        );
+    }
+
+    #[test]
+    fn auto_resolved_clients_are_changed_too() {
+        let mut engine = PaymentsEngine::new(Options {
+            auto_resolve_after: Some(1),
+            ..Options::default()
+        });
+        engine
+            .process(Transaction::deposit(1, 1, dec!(10)))
+            .unwrap();
+        assert_eq!(engine.process(Transaction::dispute(1, 1)), Ok(vec![1]));
+        assert_eq!(
+            engine.process(Transaction::deposit(2, 2, dec!(1))),
+            Ok(vec![2, 1])
+        );
+        assert_eq!(engine.client(1).unwrap().held_amount, dec!(0));
+    }
+
+    #[test]
+    fn skipped_rows_still_answer_with_the_clients_auto_resolve_changed() {
+        let mut engine = PaymentsEngine::new(Options {
+            auto_resolve_after: Some(1),
+            ..Options::default()
+        });
+        engine
+            .process(Transaction::deposit(1, 1, dec!(10)))
+            .unwrap();
+        assert_eq!(engine.process(Transaction::dispute(1, 1)), Ok(vec![1]));
+        // ignored, but the dispute it comes after is released on the way
+        assert_eq!(
+            engine.process(Transaction::withdrawal(2, 2, dec!(1))),
+            Ok(vec![1])
+        );
+        assert_eq!(engine.client(1).unwrap().held_amount, dec!(0));
+        assert_eq!(engine.process(Transaction::dispute(1, 99)), Ok(vec![]));
+    }
+
+    #[test]
+    fn run_past_the_client_limit_stops_at_the_row_adding_one_too_many() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,1.0
+deposit,1,3,1.0
+dispute,3,1,
+deposit,3,4,1.0
+deposit,2,5,1.0
+";
+        let limited = |max_clients| {
+            PaymentsEngine::new(Options {
+                max_clients: Some(max_clients),
+                ..Options::default()
+            })
+        };
+
+        let mut engine = limited(2);
+        let error = process_reader(input.as_bytes(), &mut engine).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::TooManyClients { tx: 4, max: 2 })
+        );
+        assert!(engine.client(3).is_none());
+        assert_eq!(engine.client(1).unwrap().total_amount, dec!(2));
+
+        let mut engine = limited(3);
+        process_reader(input.as_bytes(), &mut engine).unwrap();
+        assert_eq!(engine.clients().count(), 3);
+
+        // a transfer's destination counts too
+        let mut engine = limited(1);
+        engine.process(Transaction::deposit(1, 1, dec!(5))).unwrap();
+        assert_eq!(
+            engine.process(Transaction::transfer(1, 2, 2, dec!(1))),
+            Err(TransactionError::TooManyClients { tx: 2, max: 1 })
+        );
+    }
+
+    #[test]
+    fn run_past_the_transaction_limit_stops_at_the_deposit_kept_one_too_many() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,1.0
+deposit,1,3,1.0
+withdrawal,1,4,1.0
+deposit,1,5,1.0
+";
+        let limited = |max_transactions| {
+            PaymentsEngine::new(Options {
+                max_transactions: Some(max_transactions),
+                ..Options::default()
+            })
+        };
+
+        // withdrawals aren't kept, so they never count
+        let mut engine = limited(2);
+        let error = process_reader(input.as_bytes(), &mut engine).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::TooManyTransactions { tx: 5, max: 2 })
+        );
+        assert_eq!(engine.client(1).unwrap().total_amount, dec!(4));
+
+        let mut engine = limited(3);
+        process_reader(input.as_bytes(), &mut engine).unwrap();
+        assert_eq!(engine.client(1).unwrap().total_amount, dec!(5));
+    }
+
+    #[test]
+    fn auto_resolve_leaves_a_locked_clients_hold_alone() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,5.0
+dispute,1,2,
+dispute,1,1,
+chargeback,1,1,
+deposit,2,3,1.0
+deposit,2,4,1.0
+";
+        let run = |auto_resolve_after| {
+            let mut engine = PaymentsEngine::new(Options {
+                auto_resolve_after,
+                ..Options::default()
+            });
+            process_reader(input.as_bytes(), &mut engine).unwrap();
+            let client = engine.client(1).unwrap().clone();
+            (client.available_amount, client.held_amount, client.locked)
+        };
+        assert_eq!(run(None), (dec!(0), dec!(5), true));
+        assert_eq!(run(Some(3)), (dec!(0), dec!(5), true));
+    }
+
+    #[test]
+    fn transaction_can_be_parsed_from_a_line() {
+        assert_eq!(
+            "deposit,1,1,10.0".parse::<Transaction>().unwrap(),
+            Transaction::deposit(1, 1, dec!(10.0))
+        );
+        assert_eq!(
+            "dispute,1,1".parse::<Transaction>().unwrap(),
+            Transaction::dispute(1, 1)
+        );
+        assert_eq!(
+            "dispute,1,1,\n".parse::<Transaction>().unwrap(),
+            Transaction::dispute(1, 1)
+        );
+
+        for malformed in [
+            "deposit,1",
+            "deposit,one,1,10.0",
+            "refund,1,1,10.0",
+            "deposit,1,1,10.0,eu,retail,2,extra",
+        ] {
+            assert!(malformed.parse::<Transaction>().is_err(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn dispute_constructor_sets_the_type_and_no_amount() {
+        let dispute = Transaction::dispute(3, 42);
+        assert_eq!(dispute.transaction_type, TransactionType::Dispute);
+        assert_eq!(dispute.client_id, 3);
+        assert_eq!(dispute.transaction_id, 42);
+        assert_eq!(dispute.amount, None);
+        assert!(!dispute.disputed);
+        assert_eq!(dispute.disputed_by, None);
+
+        let deposit = Transaction::deposit(3, 43, dec!(1.25));
+        assert_eq!(deposit.transaction_type, TransactionType::Deposit);
+        assert_eq!(deposit.amount, Some(dec!(1.25)));
+    }
+
+    #[test]
+    fn disputing_a_withdrawn_deposit_is_flagged_with_fraud_heuristics() {
+        let mut store = MemoryStore::default();
+        let options = Options {
+            fraud_heuristics: true,
+            ..Options::default()
+        };
+
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(100)),
+            Transaction::withdrawal(1, 2, dec!(100)),
+            Transaction::dispute(1, 1),
+            // an honest dispute for comparison, the funds are still there to hold
+            Transaction::deposit(2, 3, dec!(50)),
+            Transaction::dispute(2, 3),
+        ] {
+            handle_transaction(transaction, &mut store, &options).unwrap();
+        }
+
+        let fraudster = store.clients.get(&1).unwrap();
+        assert!(fraudster.suspected_fraud);
+        assert_eq!(fraudster.available_amount, dec!(-100));
+        assert!(!store.clients.get(&2).unwrap().suspected_fraud);
+    }
+
+    #[test]
+    fn fraud_heuristics_are_off_by_default() {
+        let mut store = MemoryStore::default();
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(100)),
+            Transaction::withdrawal(1, 2, dec!(100)),
+            Transaction::dispute(1, 1),
+        ] {
+            handle_transaction(transaction, &mut store, &Options::default()).unwrap();
+        }
+        assert!(!store.clients.get(&1).unwrap().suspected_fraud);
+    }
+
+    #[test]
+    fn client_deposit() {
+        let mut client = Client::new(1);
+        let amount = dec!(10);
+        client.deposit(amount);
+        assert_eq!(client.available_amount, amount);
+        assert_eq!(client.total_amount, amount);
+    }
+
+    #[test]
+    fn client_withdraw() {
+        let mut client = Client::new(1);
+        client.deposit(dec!(15));
+        client.withdraw(dec!(7));
+        assert_eq!(client.available_amount, dec!(8));
+        assert_eq!(client.total_amount, dec!(8));
+    }
+
+    // available funds should decrease by amount,
+    //    held should increase by amount.
+    // total should remain the same
+    #[test]
+    fn client_hold() {
+        let mut client = Client::new(1);
+        client.deposit(dec!(15));
+        client.hold(dec!(5));
+        assert_eq!(client.available_amount, dec!(10));
+        assert_eq!(client.total_amount, dec!(15));
+        assert_eq!(client.held_amount, dec!(5));
+    }
+
+    // held funds should decrease by the amount
+    // available funds should increase by the maount
+    // total should remain the same
+    #[test]
+    fn client_release() {
+        let mut client = Client::new(1);
+        client.deposit(dec!(20));
+        client.hold(dec!(10));
+        client.release(dec!(5));
+        assert_eq!(client.available_amount, dec!(15));
+        assert_eq!(client.total_amount, dec!(20));
+        assert_eq!(client.held_amount, dec!(5));
+    }
+
+    #[test]
+    fn near_max_deposit_saturates_instead_of_overflowing_when_saturating() {
+        let client_id = 1;
+        let mut store = MemoryStore::default();
+        let options = Options {
+            saturate: true,
+            ..Options::default()
+        };
+
+        for (transaction_id, amount) in [(1, Decimal::MAX - dec!(1)), (2, dec!(10))] {
+            handle_transaction(
+                Transaction::deposit(client_id, transaction_id, amount),
+                &mut store,
+                &options,
+            )
+            .unwrap();
+        }
+
+        let client = store.clients.get(&client_id).unwrap();
+        assert_eq!(client.available_amount, Decimal::MAX);
+        assert_eq!(client.total_amount, Decimal::MAX);
+        assert!(store.transactions.contains_key(&2));
+    }
+
+    #[test]
+    fn deposit_that_would_overflow_is_skipped_by_default() {
+        let half = dec!(50000000000000000000000000000);
+        let mut engine = PaymentsEngine::default();
+        for transaction in [
+            Transaction::deposit(1, 1, half),
+            Transaction::deposit(1, 2, half),
+            Transaction::deposit(1, 3, dec!(1)),
+        ] {
+            engine.apply(transaction).unwrap();
+        }
+        assert_eq!(engine.client(1).unwrap().total_amount, half + dec!(1));
+        assert!(engine.transaction(2).is_none());
+        assert_eq!(engine.stats().ignored.get("overflow"), Some(&1));
+        assert_eq!(
+            engine.apply_all([Transaction::deposit(1, 4, half)], ErrorMode::Stop),
+            Err(TransactionError::Overflow { client: 1, tx: 4 })
+        );
+    }
+
+    #[test]
+    fn saturating_only_clamps_a_real_overflow_and_keeps_the_balances_adding_up() {
+        // disputing a deposit that was already withdrawn isn't an overflow, available goes
+        // negative like it does without --saturate
+        let mut engine = PaymentsEngine::new(Options {
+            saturate: true,
+            ..Options::default()
+        });
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(10)),
+            Transaction::withdrawal(1, 2, dec!(10)),
+            Transaction::dispute(1, 1),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available_amount, dec!(-10));
+        assert_eq!(client.held_amount, dec!(10));
+        assert_eq!(client.total_amount, dec!(0));
+
+        // held can only take 1 more, so only 1 leaves available
+        let mut client = Client::builder()
+            .id(1)
+            .available(dec!(1))
+            .held(Decimal::MAX - dec!(1))
+            .total(Decimal::MAX)
+            .build();
+        client.saturating_hold(dec!(5));
+        assert_eq!(client.available_amount, dec!(0));
+        assert_eq!(client.held_amount, Decimal::MAX);
+        assert!(client.is_consistent());
+    }
+
+    #[test]
+    fn client_chargeback() {
+        let mut client = Client::new(1);
+        client.deposit(dec!(5));
+        client.hold(dec!(2));
+        client.chargeback(dec!(2));
+        assert_eq!(client.held_amount, dec!(0));
+        assert_eq!(client.available_amount, dec!(3));
+        assert_eq!(client.total_amount, dec!(3));
+    }
+
+    #[test]
+    fn chargeback_takes_the_disputed_funds_out_of_held_and_locks() {
+        let (client_list, _) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(5)),
+                Transaction::deposit(1, 2, dec!(3)),
+                Transaction::dispute(1, 2),
+                Transaction::chargeback(1, 2),
+            ],
+            &Options::default(),
+        );
+        let client = client_list.get(&1).unwrap();
+        assert_eq!(client.held_amount, dec!(0));
+        assert_eq!(client.available_amount, dec!(5));
+        assert_eq!(client.total_amount, dec!(5));
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn resolved_transaction_can_be_disputed_again() {
+        let (client_list, outcomes) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(4)),
+                Transaction::dispute(1, 1),
+                Transaction::resolve(1, 1),
+                // resolving twice mustn't release the funds twice
+                Transaction::resolve(1, 1),
+                Transaction::dispute(1, 1),
+            ],
+            &Options::default(),
+        );
+        assert_eq!(
+            outcomes[2..],
+            [Outcome::Applied, Outcome::Skipped, Outcome::Applied]
+        );
+        let client = client_list.get(&1).unwrap();
+        assert_eq!(client.available_amount, dec!(0));
+        assert_eq!(client.held_amount, dec!(4));
+        assert_eq!(client.total_amount, dec!(4));
+    }
+
+    #[test]
+    fn disputing_the_same_transaction_twice_holds_the_funds_once() {
+        let (client_list, outcomes) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(4)),
+                Transaction::deposit(1, 2, dec!(6)),
+                Transaction::dispute(1, 1),
+                Transaction::dispute(1, 1),
+            ],
+            &Options::default(),
+        );
+        assert_eq!(outcomes[2..], [Outcome::Applied, Outcome::Skipped]);
+        let client = client_list.get(&1).unwrap();
+        assert_eq!(client.held_amount, dec!(4));
+        assert_eq!(client.available_amount, dec!(6));
+        assert_eq!(client.total_amount, dec!(10));
+    }
+
+    #[test]
+    fn disputing_another_clients_transaction_is_ignored() {
+        let (client_list, outcomes) = handle_all(
+            [
+                Transaction::deposit(1, 1, dec!(4)),
+                Transaction::deposit(2, 2, dec!(6)),
+                Transaction::dispute(2, 1),
+            ],
+            &Options::default(),
+        );
+        assert_eq!(outcomes[2], Outcome::Skipped);
+        let owner = client_list.get(&1).unwrap();
+        assert_eq!(owner.held_amount, dec!(0));
+        assert_eq!(owner.available_amount, dec!(4));
+        let other = client_list.get(&2).unwrap();
+        assert_eq!(other.held_amount, dec!(0));
+        assert_eq!(other.available_amount, dec!(6));
+    }
+
+    #[test]
+    fn builder_matches_the_struct_literal() {
+        let built = Client::builder()
+            .id(3)
+            .available(dec!(1.5))
+            .held(dec!(2))
+            .total(dec!(3.5))
+            .locked(true)
+            .build();
+        assert_eq!(
+            built,
+            Client {
+                id: 3,
+                available_amount: dec!(1.5),
+                held_amount: dec!(2),
+                pending_amount: None,
+                total_amount: dec!(3.5),
+                locked: true,
+                suspected_fraud: false,
+            }
+        );
+        assert_eq!(Client::builder().id(3).build(), Client::new(3));
+    }
+
+    #[test]
+    fn every_type_is_classified_as_the_kind_it_converts_back_from() {
+        for standard_type in [
+            StandardType::Deposit,
+            StandardType::Withdrawal,
+            StandardType::Fee,
+        ] {
+            let transaction_type = TransactionType::from(standard_type);
+            assert_eq!(
+                TransactionKind::from(&transaction_type),
+                TransactionKind::Standard(standard_type)
+            );
+        }
+        for meta_type in [MetaType::Dispute, MetaType::Resolve, MetaType::Chargeback] {
+            let transaction_type = TransactionType::from(meta_type);
+            assert_eq!(
+                TransactionKind::from(&transaction_type),
+                TransactionKind::Meta(meta_type)
+            );
+        }
+    }
+
+    #[test]
+    fn meta_row_with_an_amount_never_moves_funds_like_a_deposit() {
+        let input = "\
+type,client,tx,amount
+dispute,1,1,5.0
+resolve,1,1,5.0
+chargeback,1,1,5.0
+";
+        let mut engine = PaymentsEngine::default();
+        process_reader(input.as_bytes(), &mut engine).unwrap();
+        // nothing to dispute, so not even an empty client is left behind
+        assert!(engine.client(1).is_none());
+        // the standard path is the only one that keeps transactions
+        assert!(engine.transaction(1).is_none());
+    }
+
+    #[test]
+    fn client_freeze() {
+        let mut client = Client::new(1);
+        client.freeze();
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn dispute_should_hold_the_amount_specified_in_the_target_transaction() {
+        let client_id = 1;
+        let mut store = MemoryStore::default();
+        store.clients.insert(client_id, Client::new(client_id));
+        let amount = dec!(5.0000);
+        let deposit_transaction_id = 1;
+
+        handle_transaction(
+            Transaction::deposit(client_id, deposit_transaction_id, amount),
+            &mut store,
+            &Options::default(),
+        )
+        .unwrap();
+
+        handle_transaction(
+            Transaction::dispute(client_id, deposit_transaction_id),
+            &mut store,
+            &Options::default(),
+        )
+        .unwrap();
+
+        let client = store.clients.get(&client_id).unwrap();
+        assert_eq!(client.held_amount, amount);
+        assert_eq!(client.available_amount, dec!(0));
+        dbg!(client);
+    }
+
+    #[test]
+    fn partial_dispute_holds_and_releases_only_its_amount() {
+        let mut store = MemoryStore::default();
+        let mut partial_dispute = Transaction::dispute(1, 1);
+        partial_dispute.amount = Some(dec!(3.00));
+
+        for transaction in [Transaction::deposit(1, 1, dec!(10.00)), partial_dispute] {
+            handle_transaction(transaction, &mut store, &Options::default()).unwrap();
+        }
+        let client = &store.clients[&1];
+        assert_eq!(client.available_amount, dec!(7));
+        assert_eq!(client.held_amount, dec!(3));
+        assert_eq!(client.total_amount, dec!(10));
+
+        handle_transaction(Transaction::resolve(1, 1), &mut store, &Options::default()).unwrap();
+        let client = &store.clients[&1];
+        assert_eq!(client.available_amount, dec!(10));
+        assert_eq!(client.held_amount, dec!(0));
+        assert_eq!(client.total_amount, dec!(10));
+        assert_eq!(store.transactions[&1].disputed_amount, None);
+    }
+
+    #[test]
+    fn partial_dispute_charged_back_takes_only_its_amount() {
+        let mut store = MemoryStore::default();
+        let mut too_much = Transaction::dispute(1, 1);
+        too_much.amount = Some(dec!(10.01));
+        let mut partial_dispute = Transaction::dispute(1, 1);
+        partial_dispute.amount = Some(dec!(3.00));
+
+        handle_transaction(
+            Transaction::deposit(1, 1, dec!(10.00)),
+            &mut store,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            handle_transaction(too_much, &mut store, &Options::default()),
+            Err(TransactionError::DisputeExceedsAmount { tx: 1 })
+        );
+        for transaction in [partial_dispute, Transaction::chargeback(1, 1)] {
+            handle_transaction(transaction, &mut store, &Options::default()).unwrap();
+        }
+
+        let client = &store.clients[&1];
+        assert_eq!(client.available_amount, dec!(7));
+        assert_eq!(client.held_amount, dec!(0));
+        assert_eq!(client.total_amount, dec!(7));
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn resolve_releases_the_disputed_funds_and_references_the_transaction_given_by_the_tx() {
+        let client_id = 1;
+        let mut store = MemoryStore::default();
+        store.clients.insert(client_id, Client::new(client_id));
+        let deposit_transaction_id = 1;
+
+        handle_transaction(
+            Transaction::deposit(client_id, deposit_transaction_id, dec!(10.0000)),
+            &mut store,
+            &Options::default(),
+        )
+        .unwrap();
+
+        handle_transaction(
+            Transaction::dispute(client_id, deposit_transaction_id),
+            &mut store,
+            &Options::default(),
+        )
+        .unwrap();
+        assert!(
+            store
+                .transactions
+                .get(&deposit_transaction_id)
+                .unwrap()
+                .disputed
+        );
+        let client = store.clients.get(&client_id).unwrap();
+        assert_eq!(client.held_amount, dec!(10));
+        assert_eq!(client.available_amount, dec!(0));
+
+        handle_transaction(
+            Transaction::resolve(client_id, deposit_transaction_id),
+            &mut store,
+            &Options::default(),
+        )
+        .unwrap();
+
+        let client = store.clients.get(&client_id).unwrap();
+        assert_eq!(client.held_amount, dec!(0));
+        assert_eq!(client.available_amount, dec!(10));
+        assert_eq!(client.total_amount, dec!(10));
+    }
+}
@@ -1,245 +1,840 @@
+// the command line reads files and spawns threads, in the browser only the library is used
+#![cfg_attr(target_arch = "wasm32", no_main)]
+#![cfg(not(target_arch = "wasm32"))]
+
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Ok;
 use anyhow::Result;
-use clap::Parser;
-use csv::WriterBuilder;
-use rust_decimal::prelude::*;
-use rust_decimal_macros::dec;
-use serde::{Deserialize, Serialize};
-use std::io;
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use clap::{Parser, Subcommand};
+use flate2::read::MultiGzDecoder;
+use rust_decimal::Decimal;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+};
+use toy_marketplace::audit::{AuditLog, Ledger, LedgerFormat};
+use toy_marketplace::group::{GroupBy, GroupTotals};
+use toy_marketplace::totals::TypeTotals;
+use toy_marketplace::{
+    apply_transactions, compare, currency, generate, parallel, parse_transaction_type,
+    read_transactions_with_delimiter, resume, skip_invalid_rows, write_clients, Client, Column,
+    InputFormat, Options, Outcome, OutputFormat, OutputOptions, PaymentsEngine, RoundEach,
+    SortOrder, Transaction, TransactionType,
+};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::prelude::*;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    /// Transaction files, processed in the order given as if they were one long input.
+    /// Transactions are read from stdin only when no file is given
     #[clap(value_parser)]
-    file: PathBuf,
-}
+    files: Vec<PathBuf>,
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-#[serde(rename_all = "lowercase")]
-enum TransactionType {
-    Deposit,
-    Withdrawl,
-    Dispute,
-    Resolve,
-    ChargeBack,
-}
+    /// Read the input as csv, or as json with one transaction object per line
+    #[clap(long, value_enum, default_value = "csv")]
+    input_format: InputFormat,
+
+    /// What separates the fields of csv input and output, `\t` for tab separated files
+    #[clap(long, value_parser = parse_delimiter, default_value = ",")]
+    delimiter: u8,
+
+    /// Decompress every input (stdin too) with gzip. Files ending in `.gz` always are
+    #[clap(long, action)]
+    gzip: bool,
+
+    /// Move only as much as the balances can hold when a row would overflow one, instead of
+    /// skipping the row
+    #[clap(long, action)]
+    saturate: bool,
+
+    /// Let withdrawals take a client's available balance this far below zero, e.g.
+    /// `--overdraft 50` for a credit line of 50 on every account
+    #[clap(
+        long,
+        value_parser = parse_overdraft,
+        default_value = "0",
+        conflicts_with = "saturate"
+    )]
+    overdraft: Decimal,
+
+    /// Hold deposits in a pending balance until a `settle` row for the client
+    #[clap(long, action)]
+    pending: bool,
+
+    /// Skip every transaction for these client ids, e.g. `--reserved-clients=0,1,2`. Given
+    /// without a list it reserves 0, the suspense account
+    #[clap(
+        long,
+        value_parser,
+        value_delimiter = ',',
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "0"
+    )]
+    reserved_clients: Vec<u16>,
+
+    /// Only process these transaction types, e.g. `--only-types deposit,withdrawal`
+    #[clap(long, value_parser = parse_transaction_type, value_delimiter = ',')]
+    only_types: Option<Vec<TransactionType>>,
+
+    /// Stop reading after this long (e.g. `500ms`, `30s`, `5m`, `1h`), write the balances
+    /// processed so far and exit with code 124
+    #[clap(long, value_parser = parse_duration)]
+    max_runtime: Option<Duration>,
+
+    /// Stop reading after this many transactions in total, across all clients, and write
+    /// the balances for those
+    #[clap(long, value_parser)]
+    max_transactions_total: Option<usize>,
+
+    /// Stop with an error at the first transaction that would add a client past this many,
+    /// instead of holding however many clients the input has
+    #[clap(long, value_parser)]
+    max_clients: Option<usize>,
+
+    /// Stop with an error at the first deposit that would be kept past this many, instead
+    /// of holding however many the input has. Only deposits are kept, to be disputed
+    #[clap(long, value_parser)]
+    max_transactions: Option<usize>,
+
+    /// Sum applied deposits and withdrawals per value of this optional input column
+    #[clap(long, value_enum, requires = "group-output")]
+    group_by: Option<GroupBy>,
+
+    /// Where to write the --group-by totals csv
+    #[clap(long, value_parser, requires = "group-by")]
+    group_output: Option<PathBuf>,
+
+    /// Write the count and amount of every applied transaction type, across all clients,
+    /// to this csv
+    #[clap(long, value_parser)]
+    type_totals: Option<PathBuf>,
+
+    /// Round balances to the minor unit of this currency when writing them, e.g. `JPY` has
+    /// no decimals and `USD` has two
+    #[clap(long = "currency", value_name = "CURRENCY", value_parser = currency::scale)]
+    currency_scale: Option<u32>,
+
+    /// Round and pad balances to this many decimal places when writing them, half to even
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(0..=8),
+        default_value = "4",
+        conflicts_with = "currency-scale"
+    )]
+    precision: u32,
+
+    /// Write the balances as csv, as a json array of clients, or as a table to read in a
+    /// terminal
+    #[clap(long, value_enum, default_value = "csv", conflicts_with_all = &["columns", "resume-output"])]
+    output_format: OutputFormat,
+
+    /// Separate thousands with commas in `--output-format table`, csv and json are
+    /// always written plain
+    #[clap(long, action)]
+    group_digits: bool,
+
+    /// Only write these client columns after the client id, e.g. `--columns available,total`
+    #[clap(long, value_enum, value_delimiter = ',')]
+    columns: Option<Vec<Column>>,
+
+    /// Only write clients that currently have funds held by a dispute
+    #[clap(long, action)]
+    with_holds: bool,
+
+    /// Write the balances to this file instead of stdout. It only appears once every
+    /// balance has been written, a failed run leaves any earlier file in place
+    #[clap(long, short, value_parser, conflicts_with = "resume-output")]
+    output: Option<PathBuf>,
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
-#[serde(rename_all = "lowercase")]
-struct Client {
-    #[serde(rename(serialize = "client"))]
-    id: u16,
+    /// Read and apply every row without writing any balances (not to --output either),
+    /// only the rows that were skipped and how many clients there would be, to stderr
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = &["resume-output", "db", "full-audit", "ledger", "group-by", "type-totals", "serve"]
+    )]
+    dry_run: bool,
 
-    #[serde(rename(serialize = "available"))]
-    available_amount: Decimal,
+    /// Order the clients by id, or leave them in whatever order they're stored in
+    #[clap(long, value_enum, default_value = "id")]
+    sort: SortOrder,
 
-    #[serde(rename(serialize = "held"))]
-    held_amount: Decimal,
+    /// Finish the output file an interrupted run left behind, appending the clients it's
+    /// missing instead of writing to stdout
+    #[clap(long, value_parser)]
+    resume_output: Option<PathBuf>,
 
-    #[serde(rename(serialize = "total"))]
-    total_amount: Decimal,
+    /// Spread the clients over this many threads. Balances come out the same as a single
+    /// threaded run, but rows of different clients are no longer handled in input order,
+    /// so it can't be combined with the reports that depend on that order
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = &["full-audit", "ledger", "group-by", "type-totals", "auto-resolve-after", "max-clients", "max-transactions"]
+    )]
+    threads: Option<usize>,
 
-    locked: bool,
+    /// Round the balances to --precision (or the --currency's places) after every
+    /// transaction, like a ledger that only keeps rounded balances. By default they keep
+    /// every place and are only rounded when written
+    #[clap(long, value_enum, default_value = "none")]
+    round_each: RoundEach,
+
+    /// Don't let a deposit be disputed again once its dispute is resolved. Resolved and
+    /// charged back deposits are then forgotten, which keeps memory down on long runs
+    #[clap(long, action)]
+    no_redispute: bool,
+
+    /// Report clients whose disputes look like a deposit, withdraw, dispute fraud
+    #[clap(long, action)]
+    fraud_heuristics: bool,
+
+    /// Release the funds held by a dispute if this many more transactions pass without a
+    /// resolve or chargeback for it
+    #[clap(long, value_parser)]
+    auto_resolve_after: Option<usize>,
+
+    /// Print how many transactions of each type were applied or ignored, and why, to stderr
+    #[clap(long, action)]
+    summary: bool,
+
+    /// Log skipped transactions and why to stderr, `-vv` also logs every applied one
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Log the client's balances before and after every applied transaction to stderr
+    #[clap(long, action)]
+    trace: bool,
+
+    /// Stop at the first row that can't be read, reuses a transaction id or disputes,
+    /// resolves or charges back one that doesn't exist, instead of skipping it (and listing
+    /// the rows that couldn't be read at the end)
+    #[clap(long, action)]
+    strict: bool,
+
+    /// Stop with an error if a client's available and held balances ever stop adding up to
+    /// their total, instead of writing out balances that are wrong
+    #[clap(long, action)]
+    strict_invariants: bool,
+
+    /// Write every total as the client's available plus held balance, instead of the total
+    /// kept as transactions were applied, warning (with -v) about any client where the two differ
+    #[clap(long, action)]
+    recompute_totals: bool,
+
+    /// Check every client's available and held balances add up to their total once the
+    /// run is done, exiting with code 1 if any don't
+    #[clap(long, action)]
+    verify: bool,
+
+    /// Write every transaction in input order, whether it was applied or skipped and the
+    /// client's balances after it, to this csv
+    #[clap(long, value_parser)]
+    full_audit: Option<PathBuf>,
+
+    /// Write every client's transactions, applied or skipped, with their balances after
+    /// each one to this file once the run is done, grouped by client
+    #[clap(long, value_parser)]
+    ledger: Option<PathBuf>,
+
+    /// Write the ledger as csv, or as a json object of each client's entries
+    #[clap(long, value_enum, default_value = "csv", requires = "ledger")]
+    ledger_format: LedgerFormat,
+
+    /// Start from the client balances in this csv (e.g. opening balances or an earlier
+    /// run's output) instead of empty accounts. Can be given more than once; a client
+    /// locked in any of them stays locked
+    #[clap(long, value_parser)]
+    opening_balances: Vec<PathBuf>,
+
+    /// Compare the resulting balances to this expected output, report any differences
+    /// on stderr and exit with code 1 if there are some
+    #[clap(long, value_parser)]
+    compare_to: Option<PathBuf>,
+
+    /// Keep the clients and their disputable transactions in this sqlite database, carrying
+    /// on from whatever an earlier run left in it. Needs the `sqlite` feature
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = &["threads", "auto-resolve-after"]
+    )]
+    db: Option<PathBuf>,
+
+    /// Instead of reading files, serve the engine over http on this address, e.g.
+    /// `127.0.0.1:8080`: `POST /transactions` applies a json transaction and
+    /// `GET /clients/{id}` returns a client's balances. Needs the `api` feature
+    #[clap(long, value_parser, conflicts_with = "files")]
+    serve: Option<SocketAddr>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-impl Client {
-    fn new(id: u16) -> Self {
-        Self {
-            id,
-            available_amount: dec!(0),
-            held_amount: dec!(0),
-            total_amount: dec!(0),
-            locked: false,
-        }
-    }
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write a reproducible csv of random, but valid, transactions
+    Generate {
+        /// Number of distinct clients to spread the transactions over
+        #[clap(long, value_parser, default_value_t = 10)]
+        clients: u16,
 
-    // increases available and total funds by amount
-    fn deposit(&mut self, amount: Decimal) {
-        self.available_amount += amount;
-        self.total_amount += amount;
-    }
+        /// Number of rows to generate
+        #[clap(long, value_parser, default_value_t = 100)]
+        transactions: usize,
+
+        /// Seed for the random number generator, the same seed always gives the same csv
+        #[clap(long, value_parser, default_value_t = 0)]
+        seed: u64,
 
-    // decreases available and total funds by amount
-    fn withdraw(&mut self, amount: Decimal) {
-        self.available_amount -= amount;
-        self.total_amount -= amount;
+        /// File to write to instead of stdout
+        #[clap(long, short, value_parser)]
+        output: Option<PathBuf>,
+    },
+}
+
+// A single ascii character, or `\t` for a tab. The quote and line ends
+// are taken by the csv format itself
+fn parse_delimiter(value: &str) -> Result<u8> {
+    match value.as_bytes() {
+        b"\\t" => Ok(b'\t'),
+        [byte @ (b'"' | b'\n' | b'\r')] => Err(anyhow!(
+            "The delimiter can't be {:?}, csv uses it for quoting or line ends",
+            *byte as char
+        )),
+        [byte] if byte.is_ascii() => Ok(*byte),
+        _ => Err(anyhow!(
+            "The delimiter has to be one ascii character or \\t, not {:?}",
+            value
+        )),
     }
+}
 
-    // available funds should decrease by amount,
-    //    held should increase by amount.
-    // total should remain the same
-    fn hold(&mut self, amount: Decimal) {
-        self.available_amount -= amount;
-        self.held_amount += amount;
+// A decimal amount that's zero or more
+fn parse_overdraft(value: &str) -> Result<Decimal> {
+    let overdraft = Decimal::from_str(value.trim())?;
+    if overdraft.is_sign_negative() {
+        return Err(anyhow!("The overdraft can't be negative, got {}", value));
     }
+    Ok(overdraft)
+}
 
-    // held funds should decrease by the amount
-    // available funds should increase by the maount
-    // total should remain the same
-    fn release(&mut self, amount: Decimal) {
-        self.held_amount -= amount;
-        self.available_amount += amount;
+// Parses a number followed by `ms`, `s`, `m` or `h`. A bare number is seconds
+fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .with_context(|| format!("Invalid duration: {:?}", value))?;
+    let seconds = |per: u64| {
+        amount
+            .checked_mul(per)
+            .map(Duration::from_secs)
+            .ok_or_else(|| anyhow!("Duration {:?} is too long", value))
+    };
+    match unit {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "" | "s" => seconds(1),
+        "m" => seconds(60),
+        "h" => seconds(60 * 60),
+        _ => Err(anyhow!("Unknown duration unit {:?} in {:?}", unit, value)),
     }
+}
 
-    fn freeze(&mut self) {
-        self.locked = true;
+/*
+Opens the input for reading. An `http://` or `https://` argument is streamed straight from
+the server when built with the `http` feature, anything else is a path on disk.
+*/
+fn open_input(path: &Path) -> Result<Box<dyn io::Read>> {
+    let url = path
+        .to_str()
+        .filter(|path| path.starts_with("http://") || path.starts_with("https://"));
+    match url {
+        #[cfg(feature = "http")]
+        Some(url) => {
+            let response = reqwest::blocking::get(url)
+                .and_then(|response| response.error_for_status())
+                .with_context(|| format!("Fetching {}", url))?;
+            Ok(Box::new(response))
+        }
+        #[cfg(not(feature = "http"))]
+        Some(url) => Err(anyhow!(
+            "Can't read {}, reading from a url needs the `http` feature",
+            url
+        )),
+        None => Ok(Box::new(
+            File::open(path).with_context(|| format!("Opening {}", path.display()))?,
+        )),
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-struct Transaction {
-    #[serde(rename(deserialize = "type"))]
-    transaction_type: TransactionType,
+// Decompresses the input as it's read when asked to, or when it's named like a gzip file
+fn decompressed<'a>(
+    input: Box<dyn io::Read + 'a>,
+    path: Option<&Path>,
+    gzip: bool,
+) -> Box<dyn io::Read + 'a> {
+    let gzipped = gzip || path.is_some_and(|path| path.extension() == Some("gz".as_ref()));
+    if gzipped {
+        // the multi member decoder also reads files that were gzipped in parts and joined
+        Box::new(MultiGzDecoder::new(io::BufReader::new(input)))
+    } else {
+        input
+    }
+}
 
-    #[serde(rename(deserialize = "client"))]
-    client_id: u16,
+// Opens every file given, falling back to `stdin` when there are none
+fn open_inputs<'a>(
+    paths: &[PathBuf],
+    stdin: impl io::Read + 'a,
+    gzip: bool,
+) -> Result<Vec<Box<dyn io::Read + 'a>>> {
+    if paths.is_empty() {
+        return Ok(vec![decompressed(Box::new(stdin), None, gzip)]);
+    }
+    paths
+        .iter()
+        .map(|path| Ok(decompressed(open_input(path)?, Some(path), gzip)))
+        .collect()
+}
 
-    #[serde(rename(deserialize = "tx"))]
-    transaction_id: u32,
+// Reads each input to the end before moving on to the next, each with its own header
+fn read_all<'a>(
+    files: Vec<Box<dyn io::Read + 'a>>,
+    format: InputFormat,
+    delimiter: u8,
+) -> impl Iterator<Item = Result<Transaction>> + 'a {
+    files
+        .into_iter()
+        .flat_map(move |file| read_transactions_with_delimiter(file, format, delimiter))
+}
 
-    #[serde(with = "rust_decimal::serde::arbitrary_precision_option")]
-    amount: Option<Decimal>,
+// Why a run stopped before the end of its input
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum StopReason {
+    Interrupted,
+    OutOfTime,
+    TransactionCap,
+}
 
-    // bool::default is false
-    #[serde(default)]
-    disputed: bool,
+// The safety ceilings a run is held to, checked before every row
+struct Limits {
+    started: Instant,
+    max_runtime: Option<Duration>,
+    max_transactions: Option<usize>,
+    rows_read: usize,
 }
 
-impl Transaction {
-    fn amount(&self) -> Result<Decimal> {
-        match self.amount {
-            Some(amount) => Ok(amount),
-            None => Err(anyhow!("No amount field in Transaction: {:?}", self)),
+impl Limits {
+    fn new(max_runtime: Option<Duration>, max_transactions: Option<usize>) -> Self {
+        Self {
+            started: Instant::now(),
+            max_runtime,
+            max_transactions,
+            rows_read: 0,
+        }
+    }
+
+    // Counts the row about to be read, and returns why it shouldn't be if a limit is hit
+    fn check(&mut self) -> Option<StopReason> {
+        if self
+            .max_runtime
+            .is_some_and(|limit| self.started.elapsed() >= limit)
+        {
+            return Some(StopReason::OutOfTime);
+        }
+        if self
+            .max_transactions
+            .is_some_and(|limit| self.rows_read >= limit)
+        {
+            return Some(StopReason::TransactionCap);
         }
+        self.rows_read += 1;
+        None
     }
 }
 
-type TransactionList = HashMap<u32, Transaction>;
-type ClientList = HashMap<u16, Client>;
+// Where --output is written until the run has finished, next to the final file
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    path.with_file_name(name)
+}
 
-fn handle_transaction(
-    transaction: Transaction,
-    client_list: &mut ClientList,
-    // refactor to hashmap
-    transaction_list: &mut TransactionList,
+/*
+Finishes the output an earlier run was writing when it crashed: drops the row it was cut
+off in the middle of, then appends only the clients that aren't in the file yet, so
+nothing ends up written twice.
+*/
+fn resume_output<'a>(
+    path: &Path,
+    clients: impl IntoIterator<Item = &'a Client>,
+    output: OutputOptions,
 ) -> Result<()> {
-    // We always want to add the client from the transaction to the client list
-    if !client_list.contains_key(&transaction.client_id) {
-        client_list.insert(transaction.client_id, Client::new(transaction.client_id));
+    let mut partial = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Opening {}", path.display()))?;
+    let mut contents = Vec::new();
+    partial.read_to_end(&mut contents)?;
+    let written = resume::written_clients(&contents, output.delimiter.unwrap_or(b','))
+        .with_context(|| format!("Reading partial output {}", path.display()))?;
+
+    partial.set_len(written.complete_len as u64)?;
+    partial.seek(SeekFrom::End(0))?;
+    let output = OutputOptions {
+        skip_clients: written.clients,
+        skip_header: written.header,
+        ..output
     };
+    write_clients(clients, &output, partial)
+}
 
-    /*
-    We only want to add the transaction to the transaction list if it's a standard transaction.
-    Otherwise, the meta transaction would overwrite the transaction it's referencing.
+// An engine saving to, and carrying on from, the sqlite database at `path`
+#[cfg(feature = "sqlite")]
+fn open_db(path: &Path, options: Options) -> Result<PaymentsEngine> {
+    let store = toy_marketplace::store::SqliteStore::open(path)
+        .with_context(|| format!("Opening {}", path.display()))?;
+    PaymentsEngine::with_store(options, Box::new(store))
+}
 
-    In future, if needed, we could create a meta transactions list to track those, but right now,
-    it's not necessary.
-    */
-    match transaction.transaction_type {
-        TransactionType::Deposit | TransactionType::Withdrawl => {
-            handle_standard_transaction(transaction, client_list, transaction_list)?;
-        }
-        _ => handle_meta_transaction(transaction, client_list, transaction_list)?,
-    };
-    Ok(())
+#[cfg(not(feature = "sqlite"))]
+fn open_db(path: &Path, _options: Options) -> Result<PaymentsEngine> {
+    Err(anyhow!(
+        "Can't open {}, --db needs the `sqlite` feature",
+        path.display()
+    ))
 }
 
-fn handle_standard_transaction(
-    transaction: Transaction,
-    client_list: &mut ClientList,
-    transaction_list: &mut TransactionList,
-) -> Result<()> {
-    let transaction_id = transaction.transaction_id;
+// Runs the engine as an http service until the process is stopped
+#[cfg(feature = "api")]
+fn serve(addr: SocketAddr, engine: PaymentsEngine) -> Result<()> {
+    eprintln!("serving on http://{}", addr);
+    tokio::runtime::Runtime::new()?.block_on(toy_marketplace::api::serve(
+        addr,
+        Arc::new(Mutex::new(engine)),
+    ))
+}
+
+#[cfg(not(feature = "api"))]
+fn serve(addr: SocketAddr, _engine: PaymentsEngine) -> Result<()> {
+    Err(anyhow!(
+        "Can't serve on {}, --serve needs the `api` feature",
+        addr
+    ))
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
 
-    // Make hashmap
-    transaction_list.insert(transaction_id, transaction);
+    if let Some(Command::Generate {
+        clients,
+        transactions,
+        seed,
+        output,
+    }) = args.command
+    {
+        return match output {
+            Some(path) => generate::generate(clients, transactions, seed, File::create(path)?),
+            None => generate::generate(clients, transactions, seed, io::stdout().lock()),
+        };
+    }
 
-    // should never panic since we just inserted it
-    let transaction = transaction_list.get_mut(&transaction_id).unwrap();
+    if args.verbose > 0 || args.trace {
+        let level = match args.verbose {
+            0 => None,
+            1 => Some(tracing::Level::WARN),
+            2 => Some(tracing::Level::DEBUG),
+            _ => Some(tracing::Level::TRACE),
+        };
+        // --trace's lines are logged with their own target, shown whatever the verbosity
+        let trace = args.trace;
+        tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(io::stderr)
+            .finish()
+            .with(filter_fn(move |metadata| {
+                (trace && metadata.target() == "trace")
+                    || level.is_some_and(|level| *metadata.level() <= level)
+            }))
+            .init();
+    }
 
-    let client = client_list
-        .get_mut(&transaction.client_id)
-        .expect("handle_standard_transaction called on transaction with non existing client");
+    let files = open_inputs(&args.files, io::stdin().lock(), args.gzip)?;
 
-    match transaction.transaction_type {
-        TransactionType::Deposit => {
-            client.deposit(transaction.amount().context("Deposit type transaction")?)
-        }
-        TransactionType::Withdrawl => {
-            client.withdraw(transaction.amount().context("Withdrawl type transaction")?)
+    let options = Options {
+        saturate: args.saturate,
+        overdraft: args.overdraft,
+        pending: args.pending,
+        only_types: args.only_types,
+        reserved_clients: args.reserved_clients,
+        fraud_heuristics: args.fraud_heuristics,
+        auto_resolve_after: args.auto_resolve_after,
+        trace: args.trace,
+        strict_invariants: args.strict_invariants,
+        strict: args.strict,
+        no_redispute: args.no_redispute,
+        round_each: args.round_each,
+        round_precision: Some(args.currency_scale.unwrap_or(args.precision)),
+        max_clients: args.max_clients,
+        max_transactions: args.max_transactions,
+    };
+    let mut engine = match &args.db {
+        Some(path) => open_db(path, options)?,
+        None => PaymentsEngine::new(options),
+    };
+    for path in &args.opening_balances {
+        let source = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+        for id in engine.load_clients(source)? {
+            eprintln!(
+                "warning: client {} is locked in some opening balances but not others, keeping it locked",
+                id
+            );
         }
-        _ => panic!("handle_standard_transaction called with non standard transaction"),
     }
-    Ok(())
-}
+    if let Some(addr) = args.serve {
+        return serve(addr, engine);
+    }
 
-fn handle_meta_transaction(
-    transaction: Transaction,
-    client_list: &mut ClientList,
-    transaction_list: &mut TransactionList,
-) -> Result<()> {
-    let target_transaction =
-        if let Some(target) = transaction_list.get_mut(&transaction.transaction_id) {
-            target
+    // Set on SIGINT so a long run still writes out everything it processed before the Ctrl-C
+    let interrupted = Arc::new(AtomicBool::new(false));
+    #[cfg(feature = "signals")]
+    {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))?;
+    }
+
+    let mut output = OutputOptions {
+        scale: args.currency_scale,
+        precision: Some(args.precision),
+        columns: args.columns,
+        with_holds: args.with_holds,
+        format: args.output_format,
+        sort: args.sort,
+        delimiter: Some(args.delimiter),
+        group_digits: args.group_digits,
+        recompute_totals: args.recompute_totals,
+        ..OutputOptions::default()
+    };
+    let mut groups = args.group_by.map(GroupTotals::new);
+    let mut type_totals = args.type_totals.as_ref().map(|_| TypeTotals::new());
+    let mut audit = match &args.full_audit {
+        Some(path) => Some(AuditLog::new(File::create(path)?)),
+        None => None,
+    };
+    let mut ledger = args.ledger.as_ref().map(|_| Ledger::new());
+
+    // --output is written under a temporary name first and renamed into place at the end
+    let mut sink: Box<dyn io::Write> = match &args.output {
+        _ if args.dry_run => Box::new(io::sink()),
+        Some(path) => Box::new(BufWriter::new(
+            File::create(partial_path(path))
+                .with_context(|| format!("Creating {}", path.display()))?,
+        )),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let mut closed = Vec::new();
+    // closed clients already written, still wanted for --compare-to and --verify
+    let mut written_early = Vec::new();
+    let mut limits = Limits::new(args.max_runtime, args.max_transactions_total);
+    let mut stop_reason = None;
+    let should_stop = || {
+        stop_reason = if interrupted.load(Ordering::SeqCst) {
+            Some(StopReason::Interrupted)
         } else {
-            return Ok(());
+            limits.check()
         };
+        stop_reason.is_some()
+    };
+    let mut invalid_rows = Vec::new();
+    let transactions: Box<dyn Iterator<Item = Result<Transaction>>> = if args.strict {
+        Box::new(read_all(files, args.input_format, args.delimiter))
+    } else {
+        Box::new(skip_invalid_rows(
+            read_all(files, args.input_format, args.delimiter),
+            &mut invalid_rows,
+        ))
+    };
+    if let Some(threads) = args.threads {
+        // the closed clients are all written at the end, the workers can't share the output
+        let closed_clients = Mutex::new(Vec::new());
+        parallel::apply_transactions(
+            transactions,
+            &mut engine,
+            threads,
+            should_stop,
+            |transaction, outcome, engine| {
+                if transaction.transaction_type == TransactionType::CloseClient
+                    && outcome == Outcome::Applied
+                {
+                    let client = engine.client(transaction.client_id).cloned();
+                    closed_clients.lock().unwrap().extend(client);
+                }
+                Ok(())
+            },
+        )?;
+        closed = closed_clients.into_inner().unwrap();
+    } else {
+        apply_transactions(
+            transactions,
+            &mut engine,
+            should_stop,
+            |transaction, outcome, engine| {
+                if let Some(groups) = groups.as_mut() {
+                    groups.record(transaction, outcome);
+                }
+                if let Some(type_totals) = type_totals.as_mut() {
+                    type_totals.record(transaction, outcome);
+                }
+                if let Some(audit) = audit.as_mut() {
+                    audit.record(transaction, outcome, engine)?;
+                }
+                if let Some(ledger) = ledger.as_mut() {
+                    ledger.record(transaction, outcome, engine);
+                }
+                if transaction.transaction_type == TransactionType::CloseClient
+                    && outcome == Outcome::Applied
+                {
+                    let client = engine.client(transaction.client_id);
+                    if output.format != OutputFormat::Csv || args.resume_output.is_some() {
+                        // a json array or table can't be added to once written, and a resumed
+                        // output only knows what it already holds once it's opened, so these
+                        // wait for the end
+                        closed.extend(client.cloned());
+                    } else {
+                        write_clients(client, &output, &mut sink)?;
+                        output.skip_header = true;
+                        written_early.extend(client.cloned());
+                    }
+                }
+                Ok(())
+            },
+        )?;
+    }
+    if !invalid_rows.is_empty() {
+        eprintln!(
+            "warning: skipped {} rows that couldn't be read:",
+            invalid_rows.len()
+        );
+        for invalid in &invalid_rows {
+            eprintln!("  row {}: {}", invalid.row, invalid.message);
+        }
+    }
+    match stop_reason {
+        Some(StopReason::Interrupted) => {
+            eprintln!("interrupted, writing balances for the transactions processed so far")
+        }
+        Some(StopReason::OutOfTime) => {
+            eprintln!("max runtime reached, writing balances for the transactions processed so far")
+        }
+        Some(StopReason::TransactionCap) => eprintln!(
+            "warning: stopped after the first {} transactions (--max-transactions-total), writing balances for those",
+            limits.rows_read
+        ),
+        None => {}
+    }
 
-    let client = client_list
-        .get_mut(&transaction.client_id)
-        .expect("handle_standard_transaction called on transaction with non existing client");
+    match &args.resume_output {
+        Some(path) => resume_output(path, closed.iter().chain(engine.clients()), output.clone())?,
+        None => write_clients(closed.iter().chain(engine.clients()), &output, &mut sink)?,
+    }
+    sink.flush()?;
+    drop(sink);
+    if args.dry_run {
+        let skipped: usize = engine.stats().ignored.values().sum();
+        eprintln!(
+            "dry run: {} rows couldn't be read, {} were skipped, {} clients",
+            invalid_rows.len(),
+            skipped,
+            closed.len() + engine.clients().count()
+        );
+    } else if let Some(path) = &args.output {
+        std::fs::rename(partial_path(path), path)
+            .with_context(|| format!("Moving the output into {}", path.display()))?;
+    }
+    if let Some(audit) = audit {
+        audit.finish()?;
+    }
+    if let (Some(ledger), Some(path)) = (ledger, args.ledger) {
+        ledger.write(args.ledger_format, BufWriter::new(File::create(path)?))?;
+    }
+    if let (Some(groups), Some(path)) = (groups, args.group_output) {
+        groups.write(File::create(path)?)?;
+    }
+    if let (Some(type_totals), Some(path)) = (type_totals, args.type_totals) {
+        type_totals.write(File::create(path)?)?;
+    }
 
-    match transaction.transaction_type {
-        TransactionType::Dispute => client.hold(
-            target_transaction
-                .amount()
-                .context("Targeted from Dispute transaction")?,
-        ),
-        TransactionType::Resolve => {
-            if target_transaction.disputed {
-                client.release(
-                    target_transaction
-                        .amount()
-                        .context("Targeted from Resolve transaction")?,
-                );
-            }
+    if args.summary {
+        eprint!("{}", engine.stats());
+    }
+
+    if engine.options().fraud_heuristics {
+        let mut suspects: Vec<u16> = engine
+            .clients()
+            .filter(|client| client.is_suspected_fraud())
+            .map(|client| client.id())
+            .collect();
+        suspects.sort_unstable();
+        for id in suspects {
+            eprintln!(
+                "warning: client {} disputed a deposit after withdrawing it, leaving available negative",
+                id
+            );
+        }
+    }
+
+    let mut mismatched = false;
+    if let Some(path) = args.compare_to {
+        let mismatches = compare::compare(
+            closed.iter().chain(&written_early).chain(engine.clients()),
+            &output,
+            File::open(path)?,
+        )?;
+        for mismatch in &mismatches {
+            eprintln!("{}", mismatch);
         }
+        mismatched = !mismatches.is_empty();
+    }
 
-        TransactionType::ChargeBack => {
-            if target_transaction.disputed {
-                client.withdraw(
-                    target_transaction
-                        .amount()
-                        .context("Targeted from chargeback transaction")?,
+    let mut inconsistent = false;
+    if args.verify {
+        for client in closed.iter().chain(&written_early).chain(engine.clients()) {
+            if !client.is_consistent() {
+                eprintln!(
+                    "client {}: available {} + held {} + pending {} doesn't add up to total {}",
+                    client.id(),
+                    client.available(),
+                    client.held(),
+                    client.pending().unwrap_or_default(),
+                    client.total()
                 );
-                client.freeze();
+                inconsistent = true;
             }
         }
-        _ => panic!("handle_meta_transaction called on standard transaction"),
-    };
-    Ok(())
-}
-
-fn main() -> Result<()> {
-    let args = Args::parse();
-    let file = File::open(args.file)?;
-
-    let mut rdr = csv::Reader::from_reader(file);
-    let mut client_list: ClientList = HashMap::new();
-    let mut transaction_list: TransactionList = HashMap::new();
-
-    for result in rdr.deserialize() {
-        let transaction: Transaction = result?;
-        handle_transaction(transaction, &mut client_list, &mut transaction_list)?;
     }
 
-    let handle = io::stdout().lock();
-    let mut writer = WriterBuilder::new().from_writer(handle);
-    for ele in client_list.into_values() {
-        writer.serialize(ele)?;
+    if stop_reason == Some(StopReason::OutOfTime) {
+        // same code as coreutils' `timeout`, so batch jobs can tell a partial run apart
+        std::process::exit(124);
+    }
+    if mismatched || inconsistent {
+        std::process::exit(1);
     }
     Ok(())
 }
@@ -247,195 +842,298 @@ fn main() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use toy_marketplace::{process_transactions, ReadOutcome};
 
     #[test]
-    fn handle_transaction_deposit_test() {
-        let mut client_list: ClientList = HashMap::new();
-        let mut transaction_list: TransactionList = HashMap::new();
-
-        let client_id = 1;
+    fn tiny_max_runtime_stops_a_large_run_with_consistent_output() {
+        let mut input = Vec::new();
+        generate::generate(20, 200_000, 1, &mut input).unwrap();
 
-        let transaction_amount = dec!(10.4752);
-
-        handle_transaction(
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client_id,
-                transaction_id: 1,
-                amount: Some(transaction_amount),
-                disputed: false,
-            },
-            &mut client_list,
-            &mut transaction_list,
+        let mut engine = PaymentsEngine::default();
+        let mut limits = Limits::new(Some(Duration::from_millis(1)), None);
+        let outcome = process_transactions(
+            input.as_slice(),
+            &mut engine,
+            || limits.check() == Some(StopReason::OutOfTime),
+            |_, _, _| Ok(()),
         )
         .unwrap();
 
-        assert_eq!(
-            &Client {
-                id: client_id,
-                available_amount: transaction_amount,
-                held_amount: dec!(0),
-                total_amount: transaction_amount,
-                locked: false,
-            },
-            client_list.get(&client_id).unwrap()
-        );
+        assert_eq!(outcome, ReadOutcome::Stopped);
+        let unseen = generate::generate_transactions(20, 200_000, 1)
+            .iter()
+            .filter(|transaction| transaction.transaction_type == TransactionType::Deposit)
+            .filter(|transaction| engine.transaction(transaction.transaction_id).is_none())
+            .count();
+        assert!(unseen > 0);
+        for client in engine.clients() {
+            assert_eq!(client.available() + client.held(), client.total());
+        }
+    }
 
-        handle_transaction(
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client_id,
-                transaction_id: 1,
-                amount: Some(dec!(5.0000)),
-                disputed: false,
+    #[test]
+    fn transaction_cap_stops_processing_at_the_cap() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+withdrawl,1,3,2.5
+deposit,2,4,100.0
+deposit,3,5,1.0
+";
+        let mut engine = PaymentsEngine::default();
+        let mut limits = Limits::new(None, Some(3));
+        let mut stop_reason = None;
+        let outcome = process_transactions(
+            input.as_bytes(),
+            &mut engine,
+            || {
+                stop_reason = limits.check();
+                stop_reason.is_some()
             },
-            &mut client_list,
-            &mut transaction_list,
+            |_, _, _| Ok(()),
         )
         .unwrap();
 
-        assert_eq!(
-            &Client {
-                id: client_id,
-                available_amount: transaction_amount + dec!(5),
-                held_amount: dec!(0),
-                total_amount: transaction_amount + dec!(5),
-                locked: false,
-            },
-            client_list.get(&client_id).unwrap()
-        );
+        assert_eq!(outcome, ReadOutcome::Stopped);
+        assert_eq!(stop_reason, Some(StopReason::TransactionCap));
+        assert_eq!(limits.rows_read, 3);
+        assert!(engine.transaction(2).is_some());
+        assert!(engine.transaction(4).is_none());
+        assert_eq!(engine.clients().count(), 2);
+        assert_eq!(engine.client(1).unwrap().available(), dec!(7.5));
+        assert_eq!(engine.client(1).unwrap().total(), dec!(7.5));
+        assert_eq!(engine.client(2).unwrap().total(), dec!(5));
     }
 
     #[test]
-    fn client_deposit() {
-        let mut client = Client::new(1);
-        let amount = dec!(10);
-        client.deposit(amount);
-        assert_eq!(client.available_amount, amount);
-        assert_eq!(client.total_amount, amount);
+    fn parse_duration_understands_units() {
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_duration("5 days").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration(&format!("{}h", u64::MAX / 60)).is_err());
+        assert!(parse_duration(&format!("{}m", u64::MAX)).is_err());
     }
 
     #[test]
-    fn client_withdraw() {
-        let mut client = Client::new(1);
-        client.deposit(dec!(15));
-        client.withdraw(dec!(7));
-        assert_eq!(client.available_amount, dec!(8));
-        assert_eq!(client.total_amount, dec!(8));
-    }
+    fn resuming_a_truncated_output_completes_the_remaining_clients() {
+        let mut engine = PaymentsEngine::default();
+        for id in 1..=5 {
+            engine
+                .process(Transaction::deposit(id, id.into(), Decimal::from(id)))
+                .unwrap();
+        }
+        let mut full = Vec::new();
+        write_clients(engine.clients(), &OutputOptions::default(), &mut full).unwrap();
 
-    // available funds should decrease by amount,
-    //    held should increase by amount.
-    // total should remain the same
-    #[test]
-    fn client_hold() {
-        let mut client = Client::new(1);
-        client.deposit(dec!(15));
-        client.hold(dec!(5));
-        assert_eq!(client.available_amount, dec!(10));
-        assert_eq!(client.total_amount, dec!(15));
-        assert_eq!(client.held_amount, dec!(5));
-    }
-
-    // held funds should decrease by the amount
-    // available funds should increase by the maount
-    // total should remain the same
-    #[test]
-    fn client_release() {
-        let mut client = Client::new(1);
-        client.deposit(dec!(20));
-        client.hold(dec!(10));
-        client.release(dec!(5));
-        assert_eq!(client.available_amount, dec!(15));
-        assert_eq!(client.total_amount, dec!(20));
-        assert_eq!(client.held_amount, dec!(5));
+        // cut off part way through the fourth row, like a crash while writing
+        let cut = full
+            .iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte == b'\n')
+            .nth(3)
+            .unwrap()
+            .0
+            + 3;
+        let path = std::env::temp_dir().join(format!("resume-{}.csv", std::process::id()));
+        std::fs::write(&path, &full[..cut]).unwrap();
+
+        resume_output(&path, engine.clients(), OutputOptions::default()).unwrap();
+        let resumed = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut expected: Vec<&str> = std::str::from_utf8(&full).unwrap().lines().collect();
+        let mut actual: Vec<&str> = resumed.lines().collect();
+        assert_eq!(actual[0], "client,available,held,total,locked");
+        expected.sort_unstable();
+        actual.sort_unstable();
+        assert_eq!(actual, expected);
     }
 
     #[test]
-    fn client_freeze() {
-        let mut client = Client::new(1);
-        client.freeze();
-        assert!(client.locked);
+    fn a_later_file_can_dispute_a_deposit_from_an_earlier_one() {
+        let first = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,2,2.0\n";
+        let second = "type,client,tx,amount\nwithdrawl,1,3,1.0\ndispute,1,1,\n";
+        let files: Vec<Box<dyn io::Read>> =
+            vec![Box::new(first.as_bytes()), Box::new(second.as_bytes())];
+
+        let mut engine = PaymentsEngine::default();
+        apply_transactions(
+            read_all(files, InputFormat::Csv, b','),
+            &mut engine,
+            || false,
+            |_, _, _| Ok(()),
+        )
+        .unwrap();
+
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available(), dec!(1));
+        assert_eq!(client.held(), dec!(5));
+        assert_eq!(client.total(), dec!(6));
     }
 
     #[test]
-    fn dispute_should_hold_the_amount_specified_in_the_target_transaction() {
-        let client_id = 1;
-        let mut client_list: ClientList = HashMap::new();
-        client_list.insert(client_id, Client::new(client_id));
-        let amount = dec!(5.0000);
-        let mut transaction_list: TransactionList = HashMap::new();
-        let deposit_transaction_id = 1;
-
-        handle_transaction(
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client_id,
-                transaction_id: deposit_transaction_id,
-                amount: Some(amount),
-                disputed: false,
-            },
-            &mut client_list,
-            &mut transaction_list,
+    fn without_files_transactions_come_from_stdin() {
+        let stdin = "type,client,tx,amount\ndeposit,1,1,5.0\n";
+        let mut engine = PaymentsEngine::default();
+        apply_transactions(
+            read_all(
+                open_inputs(&[], stdin.as_bytes(), false).unwrap(),
+                InputFormat::Csv,
+                b',',
+            ),
+            &mut engine,
+            || false,
+            |_, _, _| Ok(()),
         )
         .unwrap();
+        assert_eq!(engine.client(1).unwrap().available(), dec!(5));
 
-        handle_transaction(
-            Transaction {
-                transaction_type: TransactionType::Dispute,
-                client_id,
-                transaction_id: deposit_transaction_id,
-                amount: None,
-                disputed: false,
-            },
-            &mut client_list,
-            &mut transaction_list,
+        // an explicit file wins over stdin
+        let error = open_inputs(
+            &[PathBuf::from("priv/missing.csv")],
+            stdin.as_bytes(),
+            false,
         )
+        .err()
         .unwrap();
+        assert!(error.to_string().contains("priv/missing.csv"));
+    }
 
-        let client = client_list.get(&client_id).unwrap();
-        assert_eq!(client.held_amount, amount);
-        assert_eq!(client.available_amount, dec!(0));
-        dbg!(client);
+    #[test]
+    fn delimiter_is_one_character_or_an_escaped_tab() {
+        assert_eq!(parse_delimiter(",").unwrap(), b',');
+        assert_eq!(parse_delimiter("\\t").unwrap(), b'\t');
+        assert_eq!(parse_delimiter("\t").unwrap(), b'\t');
+        assert!(parse_delimiter(";;").is_err());
+        assert!(parse_delimiter("").is_err());
+        for clash in ['"', '\n', '\r'] {
+            let error = parse_delimiter(&clash.to_string()).unwrap_err().to_string();
+            assert!(error.contains(&format!("{:?}", clash)), "{}", error);
+        }
     }
 
     #[test]
-    fn resolve_releases_the_disputed_funds_and_references_the_transaction_given_by_the_tx() {
-        let client_id = 1;
-        let mut client_list: ClientList = HashMap::new();
-        client_list.insert(client_id, Client::new(client_id));
-        let mut transaction_list: TransactionList = HashMap::new();
-        let deposit_transaction_id = 1;
-
-        handle_transaction(
-            Transaction {
-                transaction_type: TransactionType::Deposit,
-                client_id,
-                transaction_id: deposit_transaction_id,
-                amount: Some(dec!(10.0000)),
-                disputed: false,
-            },
-            &mut client_list,
-            &mut transaction_list,
+    fn reserved_clients_default_to_none_and_the_bare_flag_to_zero() {
+        let reserved = |args: &[&str]| {
+            Args::try_parse_from(["toy_marketplace"].iter().chain(args))
+                .unwrap()
+                .reserved_clients
+        };
+        assert_eq!(reserved(&["input.csv"]), Vec::<u16>::new());
+        assert_eq!(reserved(&["--reserved-clients", "input.csv"]), vec![0]);
+        assert_eq!(
+            reserved(&["--reserved-clients=0,7", "input.csv"]),
+            vec![0, 7]
+        );
+    }
+
+    #[test]
+    fn overdraft_is_zero_or_more() {
+        assert_eq!(parse_overdraft("0").unwrap(), Decimal::ZERO);
+        assert_eq!(parse_overdraft("12.50").unwrap(), Decimal::new(1250, 2));
+        assert!(parse_overdraft("-1").is_err());
+        assert!(parse_overdraft("lots").is_err());
+    }
+
+    #[test]
+    fn gzipped_inputs_are_decompressed() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(b"type,client,tx,amount\ndeposit,1,1,5.0\n")
+            .unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let path = std::env::temp_dir().join(format!("gzipped-{}.csv.gz", std::process::id()));
+        std::fs::write(&path, &gzipped).unwrap();
+
+        let mut engine = PaymentsEngine::default();
+        // stdin only with --gzip, the file by its extension
+        let inputs = open_inputs(&[], gzipped.as_slice(), true)
+            .unwrap()
+            .into_iter()
+            .chain(open_inputs(std::slice::from_ref(&path), io::empty(), false).unwrap());
+        apply_transactions(
+            read_all(inputs.collect(), InputFormat::Csv, b','),
+            &mut engine,
+            || false,
+            |_, _, _| Ok(()),
         )
         .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(engine.client(1).unwrap().available(), dec!(5));
+    }
 
-        handle_transaction(
-            Transaction {
-                transaction_type: TransactionType::Resolve,
-                client_id,
-                transaction_id: deposit_transaction_id,
-                amount: None,
-                disputed: false,
-            },
-            &mut client_list,
-            &mut transaction_list,
+    #[test]
+    fn args_are_consistent() {
+        use clap::CommandFactory;
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn output_is_written_next_to_the_final_file_first() {
+        assert_eq!(
+            partial_path(Path::new("out/balances.csv")),
+            Path::new("out/balances.csv.partial")
+        );
+    }
+
+    #[test]
+    fn url_input_needs_the_http_feature_or_a_server() {
+        // nothing listens on port 9 (discard), so with or without the feature this errors
+        // instead of being treated as a path on disk
+        let error = open_input(Path::new("http://127.0.0.1:9/transactions.csv"))
+            .err()
+            .unwrap();
+        assert!(!error.to_string().starts_with("Opening"));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn url_input_is_streamed_from_the_server() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        let body = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawl,1,2,2.5\n";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // read the request headers up to the blank line before answering
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/csv\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+
+        let url = format!("http://{}/transactions.csv", address);
+        let mut engine = PaymentsEngine::default();
+        process_transactions(
+            open_input(Path::new(&url)).unwrap(),
+            &mut engine,
+            || false,
+            |_, _, _| Ok(()),
         )
         .unwrap();
+        server.join().unwrap();
 
-        let client = client_list.get(&client_id).unwrap();
-        assert_eq!(client.held_amount, dec!(0));
-        assert_eq!(client.available_amount, dec!(0));
-        dbg!(client);
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available(), dec!(7.5));
+        assert_eq!(client.total(), dec!(7.5));
     }
 }
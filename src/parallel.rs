@@ -0,0 +1,261 @@
+use crate::{Outcome, PaymentsEngine, ReadOutcome, Transaction, TransactionType};
+use anyhow::{bail, Result};
+use rustc_hash::FxHashMap;
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
+
+// how many transactions can wait for a busy worker before reading the input blocks
+const QUEUE_LENGTH: usize = 1024;
+
+// the worker that owns a client, every transaction for the client goes to that one worker
+fn worker_for(client_id: u16, threads: usize) -> usize {
+    client_id as usize % threads
+}
+
+/*
+Applies every transaction to the engine like `crate::apply_transactions`, spread over
+`threads` worker threads.
+
+A transaction only ever moves its own client's funds, and disputes can only target a
+transaction of the same client, so each worker owns a fixed share of the clients and gets
+their transactions in input order. Every client ends up exactly where a serial run would
+leave them. Rows of different clients are handled in no particular order though, so
+`on_row` is shared by the workers and auto_resolve_after counts the rows of each worker
+rather than of the whole input.
+
+Transaction ids are unique across all clients though, and each worker only knows its own.
+So the reader remembers which worker every deposit went to, and a row reusing the id of
+one sent to another worker is handed over with the id marked as taken, for the worker to
+skip it as a duplicate just where a serial run would. That assumes the first deposit was
+applied and kept, one that was skipped or pruned by --no-redispute still holds its id.
+
+Transfers are the exception, they move funds to a second client. One between clients of
+two different workers stops the run with an error, since neither worker can apply it alone.
+
+The input is read on the calling thread, which is also where `should_stop` is checked.
+*/
+pub fn apply_transactions(
+    transactions: impl IntoIterator<Item = Result<Transaction>>,
+    engine: &mut PaymentsEngine,
+    threads: usize,
+    mut should_stop: impl FnMut() -> bool,
+    on_row: impl Fn(&Transaction, Outcome, &PaymentsEngine) -> Result<()> + Sync,
+) -> Result<ReadOutcome> {
+    let threads = threads.max(1);
+    let mut shards: Vec<PaymentsEngine> = (0..threads)
+        .map(|_| PaymentsEngine {
+            closed: engine.closed.clone(),
+            ..PaymentsEngine::new(engine.options.clone())
+        })
+        .collect();
+    // clients loaded before the run, from opening balances
+    for (id, client) in engine.memory.clients.drain() {
+        shards[worker_for(id, threads)]
+            .memory
+            .clients
+            .insert(id, client);
+    }
+    // the worker each kept deposit id went to
+    let mut deposits: FxHashMap<u32, usize> = FxHashMap::default();
+    for (id, transaction) in engine.memory.transactions.drain() {
+        deposits.insert(id, worker_for(transaction.client_id, threads));
+        shards[worker_for(transaction.client_id, threads)]
+            .memory
+            .transactions
+            .insert(id, transaction);
+    }
+
+    let on_row = &on_row;
+    let (read, finished) = thread::scope(|scope| {
+        let mut queues = Vec::with_capacity(threads);
+        let mut workers = Vec::with_capacity(threads);
+        for mut shard in shards {
+            let (queue, received) = mpsc::sync_channel::<(Transaction, bool)>(QUEUE_LENGTH);
+            queues.push(queue);
+            workers.push(scope.spawn(move || {
+                let mut result = Ok(ReadOutcome::Finished);
+                for (transaction, taken) in received {
+                    let id = transaction.transaction_id;
+                    // stands in for the deposit another worker has with this id
+                    if taken {
+                        shard.memory.transactions.insert(id, transaction.clone());
+                    }
+                    result = crate::apply_transactions(
+                        std::iter::once(Ok(transaction)),
+                        &mut shard,
+                        || false,
+                        on_row,
+                    );
+                    if taken {
+                        shard.memory.transactions.remove(&id);
+                    }
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                (shard, result)
+            }));
+        }
+
+        let read = (|| {
+            for result in transactions {
+                if should_stop() {
+                    return Ok(ReadOutcome::Stopped);
+                }
+                let transaction = result?;
+                let worker = worker_for(transaction.client_id, threads);
+                if let (TransactionType::Transfer, Some(destination)) =
+                    (&transaction.transaction_type, transaction.destination)
+                {
+                    if worker_for(destination, threads) != worker {
+                        bail!(
+                            "transfer {} from client {} to client {} can't be applied with --threads",
+                            transaction.transaction_id,
+                            transaction.client_id,
+                            destination
+                        );
+                    }
+                }
+                let taken = is_taken(&transaction, worker, &mut deposits);
+                // only fails once the worker gave up on an error, which joining it reports
+                if queues[worker].send((transaction, taken)).is_err() {
+                    break;
+                }
+            }
+            Ok(ReadOutcome::Finished)
+        })();
+        // lets the workers run out of transactions and finish
+        drop(queues);
+
+        let finished: Vec<_> = workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| panic::resume_unwind(panic))
+            })
+            .collect();
+        (read, finished)
+    });
+
+    let mut worker_error = None;
+    for (shard, result) in finished {
+        engine.memory.clients.extend(shard.memory.clients);
+        engine.memory.transactions.extend(shard.memory.transactions);
+        engine.closed.extend(shard.closed);
+        engine.rows += shard.rows;
+        engine.stats.merge(&shard.stats);
+        if let Err(error) = result {
+            worker_error.get_or_insert(error);
+        }
+    }
+    match worker_error {
+        Some(error) => Err(error),
+        None => read,
+    }
+}
+
+/*
+Whether the transaction reuses the id of a deposit another worker has, which a serial run
+would catch. Deposits, withdrawals, fees and transfers all need an unused id, only
+deposits are kept to hold on to theirs.
+*/
+fn is_taken(
+    transaction: &Transaction,
+    worker: usize,
+    deposits: &mut FxHashMap<u32, usize>,
+) -> bool {
+    let checked = matches!(
+        transaction.transaction_type,
+        TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Fee
+            | TransactionType::Transfer
+    );
+    if !checked {
+        return false;
+    }
+    match deposits.get(&transaction.transaction_id) {
+        Some(&owner) => owner != worker,
+        None => {
+            if transaction.transaction_type == TransactionType::Deposit {
+                deposits.insert(transaction.transaction_id, worker);
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::generate_transactions;
+    use crate::Client;
+    use rust_decimal::Decimal;
+    use std::sync::Mutex;
+
+    #[test]
+    fn parallel_run_matches_the_serial_run() {
+        let mut transactions = generate_transactions(200, 200_000, 11);
+        // a deposit id reused by a client of another worker, and a dispute of it
+        let reused = 1_000_000;
+        transactions.extend([
+            Transaction::deposit(201, reused, rust_decimal_macros::dec!(10)),
+            Transaction::deposit(202, reused, rust_decimal_macros::dec!(7)),
+            Transaction::dispute(202, reused),
+        ]);
+
+        let mut serial = PaymentsEngine::default();
+        for transaction in transactions.clone() {
+            serial.apply(transaction).unwrap();
+        }
+
+        let mut parallel = PaymentsEngine::default();
+        let rows = Mutex::new(0);
+        let outcome = apply_transactions(
+            transactions.into_iter().map(Ok),
+            &mut parallel,
+            8,
+            || false,
+            |_, _, _| {
+                *rows.lock().unwrap() += 1;
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(outcome, ReadOutcome::Finished);
+        assert_eq!(*rows.lock().unwrap(), 200_003);
+        let mut serial_clients: Vec<&Client> = serial.clients().collect();
+        let mut parallel_clients: Vec<&Client> = parallel.clients().collect();
+        serial_clients.sort_by_key(|client| client.id);
+        parallel_clients.sort_by_key(|client| client.id);
+        assert_eq!(parallel_clients, serial_clients);
+        assert_eq!(parallel.memory.transactions, serial.memory.transactions);
+        assert_eq!(parallel.client(202).unwrap().held_amount, Decimal::ZERO);
+    }
+
+    #[test]
+    fn an_error_in_a_worker_stops_the_run() {
+        let transactions = vec![
+            Ok(Transaction::deposit(1, 1, rust_decimal_macros::dec!(1))),
+            Ok(Transaction::new(TransactionType::Deposit, 1, 2, None)),
+            Ok(Transaction::deposit(2, 3, rust_decimal_macros::dec!(1))),
+            Ok(Transaction::deposit(2, 4, rust_decimal_macros::dec!(1))),
+        ];
+        // the workers are each held to the limit with the clients they have
+        let mut engine = PaymentsEngine::new(crate::Options {
+            max_transactions: Some(1),
+            ..crate::Options::default()
+        });
+        let error = apply_transactions(transactions, &mut engine, 2, || false, |_, _, _| Ok(()))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "transaction 4 would keep a transaction past the limit of 1"
+        );
+        // the row without an amount was only skipped
+        assert_eq!(engine.client(1).unwrap().total_amount, Decimal::ONE);
+    }
+}
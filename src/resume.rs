@@ -0,0 +1,64 @@
+use anyhow::Result;
+use csv::ReaderBuilder;
+use std::collections::HashSet;
+
+// What an earlier, interrupted run already got written to its output
+#[derive(Debug, Default, PartialEq)]
+pub struct Written {
+    // bytes of the output up to the end of the last complete row
+    pub complete_len: usize,
+    pub header: bool,
+    pub clients: HashSet<u16>,
+}
+
+/*
+Works out which clients a partial output already has. Only rows ending in a newline count,
+a row cut off part way by the crash is left out of `complete_len` so it can be truncated
+and written again in full.
+*/
+pub fn written_clients(partial: &[u8], delimiter: u8) -> Result<Written> {
+    let complete_len = partial
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+
+    let mut written = Written {
+        complete_len,
+        ..Written::default()
+    };
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(&partial[..complete_len]);
+    for result in rdr.records() {
+        let record = result?;
+        match record.get(0).map(str::trim) {
+            Some("client") => written.header = true,
+            Some(id) => {
+                written.clients.insert(id.parse()?);
+            }
+            None => {}
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_row_is_not_counted_as_written() {
+        let partial = b"client,available,held,total,locked\n3,1,0,1,false\n1,2,0,2,false\n2,5,0,";
+        let written = written_clients(partial, b',').unwrap();
+        assert!(written.header);
+        assert_eq!(written.clients, HashSet::from([1, 3]));
+        assert_eq!(
+            &partial[..written.complete_len],
+            b"client,available,held,total,locked\n3,1,0,1,false\n1,2,0,2,false\n"
+        );
+
+        assert_eq!(written_clients(b"clie", b',').unwrap(), Written::default());
+    }
+}
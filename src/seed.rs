@@ -0,0 +1,68 @@
+use crate::{Client, ClientList};
+use anyhow::Result;
+use csv::{ReaderBuilder, Trim};
+use std::collections::hash_map::Entry;
+use std::io::Read;
+
+/*
+Loads starting balances for clients from a csv in the same format the engine writes, so
+a run can carry on from an opening balances file or an earlier run's output.
+
+Several sources can be loaded into the same client list. When a client shows up in more
+than one, the balances from the source loaded last are kept, but a lock is never undone:
+if any source has the client locked they stay locked. The ids of clients whose locked
+state disagreed between sources are returned so the caller can warn about them.
+*/
+pub fn load_clients<R: Read>(client_list: &mut ClientList, source: R) -> Result<Vec<u16>> {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(source);
+    let mut conflicts = Vec::new();
+    for result in rdr.deserialize() {
+        let mut client: Client = result?;
+        match client_list.entry(client.id) {
+            Entry::Occupied(mut existing) => {
+                if existing.get().locked != client.locked {
+                    conflicts.push(client.id);
+                }
+                client.locked |= existing.get().locked;
+                existing.insert(client);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(client);
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn client_locked_in_one_source_and_not_the_other_stays_locked() {
+        let snapshot = "\
+client,available,held,total,locked
+1,1.5,0,1.5,true
+2,3,0,3,false
+";
+        let opening_balances = "\
+client, available, held, total, locked
+1, 2.0, 0.0, 2.0, false
+";
+        let mut client_list = ClientList::default();
+        assert!(load_clients(&mut client_list, snapshot.as_bytes())
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            load_clients(&mut client_list, opening_balances.as_bytes()).unwrap(),
+            vec![1]
+        );
+
+        let client = client_list.get(&1).unwrap();
+        assert!(client.locked);
+        assert_eq!(client.available_amount, dec!(2));
+        assert_eq!(client.total_amount, dec!(2));
+        assert!(!client_list.get(&2).unwrap().locked);
+    }
+}
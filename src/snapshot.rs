@@ -0,0 +1,133 @@
+use crate::{Client, ClientList, Transaction, TransactionList};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/*
+Everything an engine knows about its clients and transactions at one point, see
+PaymentsEngine::snapshot. Restoring it gives an engine that carries on from that point, as
+many times as needed, so one checkpoint can be continued down different paths.
+
+It serializes to anything serde can write and reads back the same. The fields the input
+and output formats leave out, like who opened a dispute and how much of it is held, are
+written here too, a restored engine would apply the rest of a dispute differently without
+them.
+*/
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct EngineState {
+    #[serde(with = "saved_clients")]
+    pub clients: ClientList,
+    #[serde(with = "saved_transactions")]
+    pub transactions: TransactionList,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedClient {
+    #[serde(flatten)]
+    client: Client,
+    suspected_fraud: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTransaction {
+    #[serde(flatten)]
+    transaction: Transaction,
+    disputed_by: Option<u16>,
+    disputed_amount: Option<Decimal>,
+}
+
+mod saved_clients {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        clients: &ClientList,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut saved: Vec<SavedClient> = clients
+            .values()
+            .map(|client| SavedClient {
+                client: client.clone(),
+                suspected_fraud: client.suspected_fraud,
+            })
+            .collect();
+        saved.sort_by_key(|saved| saved.client.id);
+        saved.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ClientList, D::Error> {
+        let saved = Vec::<SavedClient>::deserialize(deserializer)?;
+        Ok(saved
+            .into_iter()
+            .map(|saved| {
+                let mut client = saved.client;
+                client.suspected_fraud = saved.suspected_fraud;
+                (client.id, client)
+            })
+            .collect())
+    }
+}
+
+mod saved_transactions {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        transactions: &TransactionList,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut saved: Vec<SavedTransaction> = transactions
+            .values()
+            .map(|transaction| SavedTransaction {
+                transaction: transaction.clone(),
+                disputed_by: transaction.disputed_by,
+                disputed_amount: transaction.disputed_amount,
+            })
+            .collect();
+        saved.sort_by_key(|saved| saved.transaction.transaction_id);
+        saved.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<TransactionList, D::Error> {
+        let saved = Vec::<SavedTransaction>::deserialize(deserializer)?;
+        Ok(saved
+            .into_iter()
+            .map(|saved| {
+                let mut transaction = saved.transaction;
+                transaction.disputed_by = saved.disputed_by;
+                transaction.disputed_amount = saved.disputed_amount;
+                (transaction.transaction_id, transaction)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PaymentsEngine;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn state_written_to_json_reads_back_the_same() {
+        let mut engine = PaymentsEngine::default();
+        engine
+            .process(Transaction::deposit(1, 1, dec!(10.25)))
+            .unwrap();
+        engine.process(Transaction::deposit(2, 2, dec!(4))).unwrap();
+        let mut partial_dispute = Transaction::dispute(1, 1);
+        partial_dispute.amount = Some(dec!(2.5));
+        engine.process(partial_dispute).unwrap();
+
+        let state = engine.snapshot();
+        let json = serde_json::to_string(&state).unwrap();
+        let read: EngineState = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, state);
+
+        // the partial dispute still only releases what it held
+        let mut restored = PaymentsEngine::restore(read);
+        restored.process(Transaction::resolve(1, 1)).unwrap();
+        let client = restored.client(1).unwrap();
+        assert_eq!(client.available_amount, dec!(10.25));
+        assert_eq!(client.held_amount, dec!(0));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{TransactionError, TransactionType};
+use rust_decimal::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeStats {
+    pub transaction_type: TransactionType,
+    pub seen: usize,
+    pub applied: usize,
+}
+
+/*
+Counts of everything the engine was given, for --summary. Unlike --type-totals this counts
+the rows that were ignored too, by the reason they were ignored for.
+*/
+#[derive(Debug, PartialEq, Clone)]
+pub struct Stats {
+    pub types: Vec<TypeStats>,
+    // ignored rows per TransactionError::reason
+    pub ignored: BTreeMap<&'static str, usize>,
+    // amounts of applied deposits, withdrawals and fees only. Saturating, a sum too large
+    // for Decimal stops at its maximum rather than stopping the run
+    pub deposited: Decimal,
+    pub withdrawn: Decimal,
+    pub fees: Decimal,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        let types = [
+            TransactionType::Deposit,
+            TransactionType::Withdrawal,
+            TransactionType::Fee,
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+            TransactionType::Settle,
+            TransactionType::CloseClient,
+            TransactionType::Transfer,
+        ]
+        .into_iter()
+        .map(|transaction_type| TypeStats {
+            transaction_type,
+            seen: 0,
+            applied: 0,
+        })
+        .collect();
+        Self {
+            types,
+            ignored: BTreeMap::new(),
+            deposited: Decimal::ZERO,
+            withdrawn: Decimal::ZERO,
+            fees: Decimal::ZERO,
+        }
+    }
+}
+
+impl Stats {
+    pub fn record(
+        &mut self,
+        transaction_type: &TransactionType,
+        amount: Option<Decimal>,
+        result: &Result<(), TransactionError>,
+    ) {
+        let stats = self
+            .types
+            .iter_mut()
+            .find(|stats| stats.transaction_type == *transaction_type)
+            .expect("every transaction type has stats");
+        stats.seen += 1;
+        match result {
+            Ok(()) => {
+                stats.applied += 1;
+                let amount = amount.unwrap_or_default();
+                match transaction_type {
+                    TransactionType::Deposit => {
+                        self.deposited = self.deposited.saturating_add(amount)
+                    }
+                    TransactionType::Withdrawal => {
+                        self.withdrawn = self.withdrawn.saturating_add(amount)
+                    }
+                    TransactionType::Fee => self.fees = self.fees.saturating_add(amount),
+                    _ => {}
+                }
+            }
+            Err(error) => *self.ignored.entry(error.reason()).or_default() += 1,
+        }
+    }
+
+    // adds up the stats of engines that each saw part of the input
+    pub fn merge(&mut self, other: &Stats) {
+        for (stats, other) in self.types.iter_mut().zip(&other.types) {
+            stats.seen += other.seen;
+            stats.applied += other.applied;
+        }
+        for (reason, count) in &other.ignored {
+            *self.ignored.entry(reason).or_default() += count;
+        }
+        self.deposited = self.deposited.saturating_add(other.deposited);
+        self.withdrawn = self.withdrawn.saturating_add(other.withdrawn);
+        self.fees = self.fees.saturating_add(other.fees);
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "summary:")?;
+        for stats in self.types.iter().filter(|stats| stats.seen > 0) {
+            writeln!(
+                f,
+                "  {:?}: {} seen, {} applied, {} ignored",
+                stats.transaction_type,
+                stats.seen,
+                stats.applied,
+                stats.seen - stats.applied
+            )?;
+        }
+        for (reason, count) in &self.ignored {
+            writeln!(f, "  ignored, {}: {}", reason, count)?;
+        }
+        writeln!(f, "  deposited: {}", self.deposited)?;
+        writeln!(f, "  withdrawn: {}", self.withdrawn)?;
+        writeln!(f, "  fees: {}", self.fees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_reader, PaymentsEngine, Transaction};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn summary_counts_a_mixed_run() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+withdrawal,1,3,2.5
+withdrawal,2,4,50.0
+dispute,1,1,
+dispute,1,99,
+chargeback,1,1,
+deposit,1,5,1.0
+resolve,2,2,
+";
+        let mut engine = PaymentsEngine::default();
+        process_reader(input.as_bytes(), &mut engine).unwrap();
+        let stats = engine.stats();
+
+        let counts: Vec<(TransactionType, usize, usize)> = stats
+            .types
+            .iter()
+            .filter(|stats| stats.seen > 0)
+            .map(|stats| (stats.transaction_type.clone(), stats.seen, stats.applied))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                (TransactionType::Deposit, 3, 2),
+                (TransactionType::Withdrawal, 2, 1),
+                (TransactionType::Dispute, 2, 1),
+                (TransactionType::Resolve, 1, 0),
+                (TransactionType::Chargeback, 1, 1),
+            ]
+        );
+        assert_eq!(
+            stats.ignored,
+            BTreeMap::from([
+                ("account locked", 1),
+                ("insufficient funds", 1),
+                ("not disputed", 1),
+                ("unknown transaction", 1),
+            ])
+        );
+        assert_eq!(stats.deposited, dec!(15));
+        assert_eq!(stats.withdrawn, dec!(2.5));
+        assert_eq!(
+            stats.to_string(),
+            "\
+summary:
+  Deposit: 3 seen, 2 applied, 1 ignored
+  Withdrawal: 2 seen, 1 applied, 1 ignored
+  Dispute: 2 seen, 1 applied, 1 ignored
+  Resolve: 1 seen, 0 applied, 1 ignored
+  Chargeback: 1 seen, 1 applied, 0 ignored
+  ignored, account locked: 1
+  ignored, insufficient funds: 1
+  ignored, not disputed: 1
+  ignored, unknown transaction: 1
+  deposited: 15.0
+  withdrawn: 2.5
+  fees: 0
+"
+        );
+    }
+
+    #[test]
+    fn fees_are_summed_and_totals_too_large_saturate() {
+        let half = dec!(50000000000000000000000000000);
+        let mut engine = PaymentsEngine::default();
+        for transaction in [
+            Transaction::deposit(1, 1, half),
+            Transaction::deposit(2, 2, half),
+            Transaction::new(TransactionType::Fee, 1, 3, Some(dec!(1.5))),
+            Transaction::new(TransactionType::Fee, 2, 4, Some(dec!(0.25))),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        let stats = engine.stats();
+        assert_eq!(stats.deposited, Decimal::MAX);
+        assert_eq!(stats.fees, dec!(1.75));
+        assert!(stats.to_string().ends_with("  fees: 1.75\n"));
+    }
+}
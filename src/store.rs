@@ -0,0 +1,385 @@
+use crate::{Client, ClientList, Transaction, TransactionList};
+use anyhow::Result;
+use std::fmt;
+
+/*
+Somewhere outside the process the engine's clients and transactions are kept, so a run can
+stop and a later one carry on from where it left off instead of replaying every input.
+
+The engine still applies transactions to its own maps, the store is written through as
+each row is handled and read back in full when an engine is created with it. Without one,
+the default, everything only ever lives in memory.
+*/
+pub trait TransactionStore: fmt::Debug + Send {
+    // Fills the lists with everything saved by earlier runs
+    fn load(&mut self, clients: &mut ClientList, transactions: &mut TransactionList) -> Result<()>;
+
+    // Inserts the client, or replaces the balances saved for them
+    fn save_client(&mut self, client: &Client) -> Result<()>;
+
+    fn remove_client(&mut self, id: u16) -> Result<()>;
+
+    // Inserts the transaction, or replaces its dispute state
+    fn save_transaction(&mut self, transaction: &Transaction) -> Result<()>;
+
+    fn remove_transaction(&mut self, id: u32) -> Result<()>;
+}
+
+/*
+Where the handlers read and write clients and transactions while applying them. They only
+go through these methods, so the balances can live somewhere other than memory without
+the business logic changing.
+
+Clients and transactions are handed out as copies, a changed one has to be upserted or
+inserted again to be kept.
+*/
+pub trait Store {
+    fn get_client(&self, id: u16) -> Option<Client>;
+
+    // Inserts the client, or replaces the one with the same id
+    fn upsert_client(&mut self, client: Client);
+
+    fn get_transaction(&self, id: u32) -> Option<Transaction>;
+
+    // Inserts the transaction, or replaces the one with the same id
+    fn insert_transaction(&mut self, transaction: Transaction);
+
+    fn remove_transaction(&mut self, id: u32);
+
+    fn iter_clients(&self) -> Box<dyn Iterator<Item = Client> + '_>;
+
+    // for --max-clients and --max-transactions
+    fn client_count(&self) -> usize;
+
+    fn transaction_count(&self) -> usize;
+}
+
+// The default store, the client and transaction maps the engine has always kept
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    pub clients: ClientList,
+    pub transactions: TransactionList,
+}
+
+impl Store for MemoryStore {
+    fn get_client(&self, id: u16) -> Option<Client> {
+        self.clients.get(&id).cloned()
+    }
+
+    fn upsert_client(&mut self, client: Client) {
+        self.clients.insert(client.id, client);
+    }
+
+    fn get_transaction(&self, id: u32) -> Option<Transaction> {
+        self.transactions.get(&id).cloned()
+    }
+
+    fn insert_transaction(&mut self, transaction: Transaction) {
+        self.transactions
+            .insert(transaction.transaction_id, transaction);
+    }
+
+    fn remove_transaction(&mut self, id: u32) {
+        self.transactions.remove(&id);
+    }
+
+    fn iter_clients(&self) -> Box<dyn Iterator<Item = Client> + '_> {
+        Box::new(self.clients.values().cloned())
+    }
+
+    fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    fn transaction_count(&self) -> usize {
+        self.transactions.len()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::TransactionStore;
+    use crate::{parse_transaction_type, Client, ClientList, Transaction, TransactionList};
+    use anyhow::Result;
+    use rusqlite::{params, Connection, Row};
+    use rust_decimal::Decimal;
+    use std::path::Path;
+    use std::str::FromStr;
+
+    // amounts are stored as text, sqlite's own numbers would round them
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS clients (
+            id INTEGER PRIMARY KEY,
+            available TEXT NOT NULL,
+            held TEXT NOT NULL,
+            pending TEXT,
+            total TEXT NOT NULL,
+            locked INTEGER NOT NULL,
+            suspected_fraud INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS transactions (
+            id INTEGER PRIMARY KEY,
+            type TEXT NOT NULL,
+            client INTEGER NOT NULL,
+            amount TEXT,
+            disputed INTEGER NOT NULL,
+            disputed_by INTEGER,
+            disputed_amount TEXT,
+            region TEXT,
+            segment TEXT
+        );
+    ";
+
+    // Keeps the clients and disputable transactions in a sqlite database, see --db
+    #[derive(Debug)]
+    pub struct SqliteStore {
+        connection: Connection,
+    }
+
+    impl SqliteStore {
+        // Opens the database at `path`, creating it and its tables if they don't exist yet
+        pub fn open(path: &Path) -> Result<Self> {
+            Self::new(Connection::open(path)?)
+        }
+
+        fn new(connection: Connection) -> Result<Self> {
+            // every row is its own write, the journal keeps those from syncing each time
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+            connection.pragma_update(None, "synchronous", "NORMAL")?;
+            connection.execute_batch(SCHEMA)?;
+            Ok(Self { connection })
+        }
+    }
+
+    fn decimal(row: &Row, column: &str) -> Result<Decimal> {
+        Ok(Decimal::from_str(&row.get::<_, String>(column)?)?)
+    }
+
+    fn optional_decimal(row: &Row, column: &str) -> Result<Option<Decimal>> {
+        row.get::<_, Option<String>>(column)?
+            .map(|amount| Ok(Decimal::from_str(&amount)?))
+            .transpose()
+    }
+
+    impl TransactionStore for SqliteStore {
+        fn load(
+            &mut self,
+            clients: &mut ClientList,
+            transactions: &mut TransactionList,
+        ) -> Result<()> {
+            let mut statement = self.connection.prepare("SELECT * FROM clients")?;
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                let client = Client {
+                    id: row.get("id")?,
+                    available_amount: decimal(row, "available")?,
+                    held_amount: decimal(row, "held")?,
+                    pending_amount: optional_decimal(row, "pending")?,
+                    total_amount: decimal(row, "total")?,
+                    locked: row.get("locked")?,
+                    suspected_fraud: row.get("suspected_fraud")?,
+                };
+                clients.insert(client.id, client);
+            }
+
+            let mut statement = self.connection.prepare("SELECT * FROM transactions")?;
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                let transaction = Transaction {
+                    transaction_type: parse_transaction_type(&row.get::<_, String>("type")?)?,
+                    client_id: row.get("client")?,
+                    transaction_id: row.get("id")?,
+                    amount: optional_decimal(row, "amount")?,
+                    disputed: row.get("disputed")?,
+                    disputed_by: row.get("disputed_by")?,
+                    disputed_amount: optional_decimal(row, "disputed_amount")?,
+                    region: row.get("region")?,
+                    segment: row.get("segment")?,
+                    // only deposits are kept, never transfers
+                    destination: None,
+                };
+                transactions.insert(transaction.transaction_id, transaction);
+            }
+            Ok(())
+        }
+
+        fn save_client(&mut self, client: &Client) -> Result<()> {
+            self.connection
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO clients
+                     (id, available, held, pending, total, locked, suspected_fraud)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )?
+                .execute(params![
+                    client.id,
+                    client.available_amount.to_string(),
+                    client.held_amount.to_string(),
+                    client.pending_amount.map(|amount| amount.to_string()),
+                    client.total_amount.to_string(),
+                    client.locked,
+                    client.suspected_fraud,
+                ])?;
+            Ok(())
+        }
+
+        fn remove_client(&mut self, id: u16) -> Result<()> {
+            self.connection
+                .prepare_cached("DELETE FROM clients WHERE id = ?1")?
+                .execute([id])?;
+            Ok(())
+        }
+
+        fn save_transaction(&mut self, transaction: &Transaction) -> Result<()> {
+            // the names the csv uses, which parse_transaction_type reads back
+            let transaction_type = format!("{:?}", transaction.transaction_type).to_lowercase();
+            self.connection
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO transactions
+                     (id, type, client, amount, disputed, disputed_by, disputed_amount,
+                      region, segment)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                )?
+                .execute(params![
+                    transaction.transaction_id,
+                    transaction_type,
+                    transaction.client_id,
+                    transaction.amount.map(|amount| amount.to_string()),
+                    transaction.disputed,
+                    transaction.disputed_by,
+                    transaction.disputed_amount.map(|amount| amount.to_string()),
+                    transaction.region,
+                    transaction.segment,
+                ])?;
+            Ok(())
+        }
+
+        fn remove_transaction(&mut self, id: u32) -> Result<()> {
+            self.connection
+                .prepare_cached("DELETE FROM transactions WHERE id = ?1")?
+                .execute([id])?;
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::generate::generate_transactions;
+        use crate::{Options, PaymentsEngine};
+
+        #[test]
+        fn resuming_from_the_db_matches_a_single_run() {
+            let transactions = generate_transactions(20, 2_000, 5);
+            let path = std::env::temp_dir().join(format!(
+                "toy_marketplace_resume_{}.sqlite",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+
+            let mut single = PaymentsEngine::default();
+            for transaction in transactions.clone() {
+                single.apply(transaction).unwrap();
+            }
+
+            let (first, rest) = transactions.split_at(transactions.len() / 2);
+            let store = SqliteStore::open(&path).unwrap();
+            let mut engine =
+                PaymentsEngine::with_store(Options::default(), Box::new(store)).unwrap();
+            for transaction in first.iter().cloned() {
+                engine.apply(transaction).unwrap();
+            }
+            drop(engine);
+
+            let store = SqliteStore::open(&path).unwrap();
+            let mut engine =
+                PaymentsEngine::with_store(Options::default(), Box::new(store)).unwrap();
+            for transaction in rest.iter().cloned() {
+                engine.apply(transaction).unwrap();
+            }
+            std::fs::remove_file(&path).unwrap();
+
+            let mut resumed: Vec<&Client> = engine.clients().collect();
+            let mut expected: Vec<&Client> = single.clients().collect();
+            resumed.sort_by_key(|client| client.id);
+            expected.sort_by_key(|client| client.id);
+            assert_eq!(resumed, expected);
+            assert_eq!(engine.memory.transactions, single.memory.transactions);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{handle_transaction, Options};
+    use rust_decimal_macros::dec;
+    use std::collections::BTreeMap;
+
+    // a store that isn't the engine's maps, the handlers can only reach it through Store
+    #[derive(Default)]
+    struct OrderedStore {
+        clients: BTreeMap<u16, Client>,
+        transactions: BTreeMap<u32, Transaction>,
+    }
+
+    impl Store for OrderedStore {
+        fn get_client(&self, id: u16) -> Option<Client> {
+            self.clients.get(&id).cloned()
+        }
+
+        fn upsert_client(&mut self, client: Client) {
+            self.clients.insert(client.id, client);
+        }
+
+        fn get_transaction(&self, id: u32) -> Option<Transaction> {
+            self.transactions.get(&id).cloned()
+        }
+
+        fn insert_transaction(&mut self, transaction: Transaction) {
+            self.transactions
+                .insert(transaction.transaction_id, transaction);
+        }
+
+        fn remove_transaction(&mut self, id: u32) {
+            self.transactions.remove(&id);
+        }
+
+        fn iter_clients(&self) -> Box<dyn Iterator<Item = Client> + '_> {
+            Box::new(self.clients.values().cloned())
+        }
+
+        fn client_count(&self) -> usize {
+            self.clients.len()
+        }
+
+        fn transaction_count(&self) -> usize {
+            self.transactions.len()
+        }
+    }
+
+    #[test]
+    fn deposit_and_dispute_through_any_store() {
+        let mut store = OrderedStore::default();
+        for transaction in [
+            Transaction::deposit(2, 1, dec!(3)),
+            Transaction::deposit(1, 2, dec!(10)),
+            Transaction::dispute(1, 2),
+        ] {
+            handle_transaction(transaction, &mut store, &Options::default()).unwrap();
+        }
+
+        let clients: Vec<Client> = store.iter_clients().collect();
+        assert_eq!(
+            clients.iter().map(|client| client.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(clients[0].available_amount, dec!(0));
+        assert_eq!(clients[0].held_amount, dec!(10));
+        assert_eq!(clients[0].total_amount, dec!(10));
+        assert!(store.get_transaction(2).unwrap().disputed);
+        assert_eq!(store.get_client(2).unwrap().available_amount, dec!(3));
+    }
+}
@@ -0,0 +1,21 @@
+use crate::{PaymentsEngine, Transaction};
+use anyhow::Result;
+use tokio_stream::{Stream, StreamExt};
+
+/*
+Applies transactions as they arrive on `stream`, e.g. rows read off a socket, instead of
+from a file that's already complete. Like `crate::process_reader`, only for a stream.
+
+Only waiting for the next transaction is async, each one is applied to the engine as soon
+as it arrives and the engine itself is the same synchronous one.
+*/
+pub async fn process_stream<S>(stream: S, engine: &mut PaymentsEngine) -> Result<()>
+where
+    S: Stream<Item = Result<Transaction>>,
+{
+    let mut stream = std::pin::pin!(stream);
+    while let Some(result) = stream.next().await {
+        engine.apply(result?)?;
+    }
+    Ok(())
+}
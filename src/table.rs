@@ -0,0 +1,176 @@
+use crate::{currency, normalize_zero, Client, OutputOptions, PRECISION};
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::io::Write;
+
+const HEADERS: [&str; 5] = ["Client", "Available", "Held", "Total", "Locked"];
+
+/*
+Writes the clients as a table lined up for reading in a terminal, see --output-format table:
+
+  Client | Available |   Held |  Total | Locked
+  -------+-----------+--------+--------+-------
+       1 |    1.5000 | 0.0000 | 1.5000 | false
+
+Every amount is padded to the same number of places so the decimal points line up, and
+numbers are right aligned. With `group_digits` thousands are separated by commas, which is
+why this is only ever for people, csv and json stay plain for machines.
+*/
+pub fn write_table<'a, W: Write>(
+    clients: impl IntoIterator<Item = &'a Client>,
+    output: &OutputOptions,
+    mut writer: W,
+) -> Result<()> {
+    let places = output.scale.or(output.precision).unwrap_or(PRECISION);
+    let amount = |amount: Decimal| {
+        let amount = normalize_zero(currency::rescale(amount, places), places).to_string();
+        if output.group_digits {
+            group_digits(&amount)
+        } else {
+            amount
+        }
+    };
+    let rows: Vec<[String; 5]> = clients
+        .into_iter()
+        .map(|client| {
+            [
+                client.id.to_string(),
+                amount(client.available_amount),
+                amount(client.held_amount),
+                amount(client.total_amount),
+                client.locked.to_string(),
+            ]
+        })
+        .collect();
+
+    let mut widths = HEADERS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let line = |cells: [&str; 5]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(column, (cell, width))| match column {
+                // locked is the only column that isn't a number
+                4 => format!("{:<width$}", cell),
+                _ => format!("{:>width$}", cell),
+            })
+            .collect();
+        padded.join(" | ").trim_end().to_string()
+    };
+    writeln!(writer, "{}", line(HEADERS))?;
+    let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    writeln!(writer, "{}", rule.join("-+-"))?;
+    for row in &rows {
+        writeln!(writer, "{}", line(row.each_ref().map(String::as_str)))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// 1234567.8900 as 1,234,567.8900
+fn group_digits(amount: &str) -> String {
+    let (sign, unsigned) = match amount.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", amount),
+    };
+    let (whole, fraction) = match unsigned.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let mut grouped = String::from(sign);
+    for (index, digit) in whole.chars().enumerate() {
+        if index > 0 && (whole.len() - index) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    if let Some(fraction) = fraction {
+        grouped.push('.');
+        grouped.push_str(fraction);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{write_clients, OutputFormat};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn two_clients_as_a_table() {
+        let clients = [
+            Client::builder()
+                .id(1)
+                .available(dec!(1.5))
+                .total(dec!(1.5))
+                .build(),
+            Client::builder()
+                .id(12)
+                .available(dec!(-3))
+                .held(dec!(120.25))
+                .total(dec!(117.25))
+                .locked(true)
+                .build(),
+        ];
+        let output = OutputOptions {
+            format: OutputFormat::Table,
+            ..OutputOptions::default()
+        };
+        let mut table = Vec::new();
+        write_clients(&clients, &output, &mut table).unwrap();
+
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "\
+Client | Available |     Held |    Total | Locked
+-------+-----------+----------+----------+-------
+     1 |    1.5000 |   0.0000 |   1.5000 | false
+    12 |   -3.0000 | 120.2500 | 117.2500 | true
+"
+        );
+    }
+
+    #[test]
+    fn large_balance_is_grouped_in_the_table_but_not_the_csv() {
+        let client = Client::builder()
+            .id(1)
+            .available(dec!(1234567.89))
+            .total(dec!(1234567.89))
+            .build();
+        let table = OutputOptions {
+            format: OutputFormat::Table,
+            group_digits: true,
+            ..OutputOptions::default()
+        };
+        let csv = OutputOptions {
+            group_digits: true,
+            ..OutputOptions::default()
+        };
+
+        let mut output = Vec::new();
+        write_clients([&client], &table, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("1,234,567.8900"), "{}", output);
+
+        let mut output = Vec::new();
+        write_clients([&client], &csv, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("1234567.8900,"), "{}", output);
+        assert!(!output.contains("1,234"), "{}", output);
+    }
+
+    #[test]
+    fn digits_are_grouped_in_threes_from_the_point() {
+        assert_eq!(group_digits("0.0000"), "0.0000");
+        assert_eq!(group_digits("999"), "999");
+        assert_eq!(group_digits("1000"), "1,000");
+        assert_eq!(group_digits("-123456.5"), "-123,456.5");
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{Outcome, Transaction, TransactionType};
+use anyhow::Result;
+use csv::WriterBuilder;
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct TypeTotal {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    // applied transactions of this type
+    count: usize,
+    // for disputes, resolves and chargebacks, the amount of the transactions they targeted
+    amount: Decimal,
+}
+
+/*
+Grand totals per transaction type across every client, for --type-totals. Only applied
+transactions count, so a skipped withdrawal or a dispute of an unknown tx adds nothing.
+*/
+pub struct TypeTotals {
+    totals: Vec<TypeTotal>,
+    // amounts of the deposits and withdrawals seen so far, to look up what a dispute,
+    // resolve or chargeback moved since those rows have no amount of their own
+    amounts: HashMap<u32, Decimal>,
+}
+
+impl Default for TypeTotals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypeTotals {
+    pub fn new() -> Self {
+        let totals = [
+            TransactionType::Deposit,
+            TransactionType::Withdrawal,
+            TransactionType::Fee,
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+            TransactionType::Transfer,
+        ]
+        .into_iter()
+        .map(|transaction_type| TypeTotal {
+            transaction_type,
+            count: 0,
+            amount: Decimal::ZERO,
+        })
+        .collect();
+        Self {
+            totals,
+            amounts: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, transaction: &Transaction, outcome: Outcome) {
+        if outcome != Outcome::Applied {
+            return;
+        }
+        let amount = match transaction.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Fee => {
+                let amount = transaction.amount.unwrap_or_default();
+                self.amounts.insert(transaction.transaction_id, amount);
+                amount
+            }
+            // never disputed, so there's no need to remember it
+            TransactionType::Transfer => transaction.amount.unwrap_or_default(),
+            _ => self
+                .amounts
+                .get(&transaction.transaction_id)
+                .copied()
+                .unwrap_or_default(),
+        };
+        if let Some(total) = self
+            .totals
+            .iter_mut()
+            .find(|total| total.transaction_type == transaction.transaction_type)
+        {
+            total.count += 1;
+            total.amount += amount;
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = WriterBuilder::new().from_writer(writer);
+        for total in &self.totals {
+            writer.serialize(total)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_transactions, PaymentsEngine};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn deposits_and_withdrawals_are_totalled_across_clients() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,7.5
+withdrawl,1,3,2.5
+deposit,1,4,1.0
+dispute,2,2,
+";
+        let mut engine = PaymentsEngine::default();
+        let mut totals = TypeTotals::new();
+        process_transactions(
+            input.as_bytes(),
+            &mut engine,
+            || false,
+            |transaction, outcome, _| {
+                totals.record(transaction, outcome);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(totals.totals[0].count, 3);
+        assert_eq!(totals.totals[0].amount, dec!(18.5));
+        assert_eq!(totals.totals[1].count, 1);
+        assert_eq!(totals.totals[1].amount, dec!(2.5));
+        assert_eq!(totals.totals[3].amount, dec!(7.5));
+
+        let mut output = Vec::new();
+        totals.write(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+type,count,amount
+deposit,3,18.5
+withdrawal,1,2.5
+fee,0,0
+dispute,1,7.5
+resolve,0,0
+chargeback,0,0
+transfer,0,0
+"
+        );
+    }
+}
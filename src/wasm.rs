@@ -0,0 +1,54 @@
+use crate::{process_reader, write_clients, OutputFormat, OutputOptions, PaymentsEngine};
+use anyhow::Result;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/*
+The engine in the browser, to preview balances before the transactions are submitted.
+Built with `wasm-pack build --target web`, javascript then calls `process_csv(text)`.
+
+Takes a whole csv of transactions and answers with the clients it leaves behind, as the
+same json array `--output-format json` writes. There's no error type across the boundary,
+a csv the engine can't get through is answered with `{"error": "..."}` instead.
+*/
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn process_csv(input: &str) -> String {
+    clients_json(input)
+        .unwrap_or_else(|error| serde_json::json!({ "error": error.to_string() }).to_string())
+}
+
+fn clients_json(input: &str) -> Result<String> {
+    let mut engine = PaymentsEngine::default();
+    process_reader(input.as_bytes(), &mut engine)?;
+    let output = OutputOptions {
+        format: OutputFormat::Json,
+        ..OutputOptions::default()
+    };
+    let mut json = Vec::new();
+    write_clients(engine.clients(), &output, &mut json)?;
+    Ok(String::from_utf8(json)?.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn csv_in_client_json_out() {
+        let input = "type,client,tx,amount
+deposit,1,1,10.5
+deposit,2,2,3
+dispute,1,1,
+";
+        let clients: Value = serde_json::from_str(&process_csv(input)).unwrap();
+        assert_eq!(clients.as_array().unwrap().len(), 2);
+        assert_eq!(clients[0]["client"], 1);
+        assert_eq!(clients[0]["held"], "10.5000");
+        assert_eq!(clients[1]["available"], "3.0000");
+
+        let error: Value =
+            serde_json::from_str(&process_csv("type,client,tx\ndeposit,1,1\n")).unwrap();
+        assert_eq!(error["error"], "missing required column: amount");
+    }
+}
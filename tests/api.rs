@@ -0,0 +1,64 @@
+#![cfg(feature = "api")]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use toy_marketplace::{api, PaymentsEngine};
+
+// A bare http/1.1 request, returning the status code and body of the response
+fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+#[test]
+fn posted_deposit_and_dispute_show_in_the_client() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    let engine = Arc::new(Mutex::new(PaymentsEngine::default()));
+    runtime.spawn(async move { axum::serve(listener, api::router(engine)).await });
+
+    let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 10.5}"#;
+    assert_eq!(
+        request(addr, "POST", "/transactions", deposit),
+        (200, "\"applied\"".to_string())
+    );
+    let dispute = r#"{"type": "dispute", "client": 1, "tx": 1}"#;
+    assert_eq!(
+        request(addr, "POST", "/transactions", dispute),
+        (200, "\"applied\"".to_string())
+    );
+    // a second dispute of the same deposit is skipped, like it would be in a file
+    assert_eq!(
+        request(addr, "POST", "/transactions", dispute),
+        (200, "\"skipped\"".to_string())
+    );
+
+    let (status, body) = request(addr, "GET", "/clients/1", "");
+    assert_eq!(status, 200);
+    let client: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(client["client"], 1);
+    assert_eq!(client["available"], "0.0");
+    assert_eq!(client["held"], "10.5");
+    assert_eq!(client["total"], "10.5");
+    assert_eq!(client["locked"], false);
+
+    assert_eq!(request(addr, "GET", "/clients/2", "").0, 404);
+}
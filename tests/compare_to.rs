@@ -0,0 +1,43 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_toy_marketplace"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn a_run_matches_the_output_it_wrote() {
+    // rounded when written, and a closed client whose row is written early
+    let input = "\
+type,client,tx,amount
+deposit,1,1,1.00005
+deposit,2,2,1.0
+closeclient,2,3,
+";
+    let written = run(&[], input);
+    assert!(written.status.success());
+    let expected = std::env::temp_dir().join(format!(
+        "toy_marketplace_compare_to_{}.csv",
+        std::process::id()
+    ));
+    std::fs::write(&expected, &written.stdout).unwrap();
+
+    let compared = run(&["--compare-to", expected.to_str().unwrap()], input);
+    std::fs::remove_file(&expected).unwrap();
+    let stderr = String::from_utf8(compared.stderr).unwrap();
+    assert!(compared.status.success(), "{}", stderr);
+    assert!(stderr.is_empty(), "{}", stderr);
+}
@@ -0,0 +1,27 @@
+use std::process::Command;
+
+fn dry_run(input: &str, strict: bool) -> std::process::Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_toy_marketplace"));
+    command.arg(input).arg("--dry-run");
+    if strict {
+        command.arg("--strict");
+    }
+    command.output().unwrap()
+}
+
+#[test]
+fn dry_run_of_a_valid_file_writes_no_balances() {
+    let output = dry_run("priv/small_sample.csv", false);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("0 rows couldn't be read"), "{}", stderr);
+    assert!(stderr.contains("2 clients"), "{}", stderr);
+}
+
+#[test]
+fn strict_dry_run_of_an_invalid_file_fails() {
+    let output = dry_run("priv/garbage_row.csv", true);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
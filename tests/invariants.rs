@@ -0,0 +1,182 @@
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use toy_marketplace::{Client, ErrorMode, PaymentsEngine, Transaction, TransactionType};
+
+const CLIENTS: u16 = 3;
+
+// One row of a random input, before it's given a transaction id
+#[derive(Debug, Clone)]
+enum Step {
+    Deposit(u16, Decimal),
+    Withdrawal(u16, Decimal),
+    // the meta rows pick their target by an index into every id handed out so far, one
+    // past the end is an id that doesn't exist yet, like disputing before depositing
+    Dispute(u16, usize),
+    Resolve(u16, usize),
+    Chargeback(u16, usize),
+}
+
+fn amount() -> impl Strategy<Value = Decimal> {
+    // up to 100.0000, at the four places the spec allows
+    (1i64..=1_000_000).prop_map(|minor| Decimal::new(minor, 4))
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    let client = 1..=CLIENTS;
+    let target = 0usize..64;
+    prop_oneof![
+        3 => (client.clone(), amount()).prop_map(|(c, amount)| Step::Deposit(c, amount)),
+        2 => (client.clone(), amount()).prop_map(|(c, amount)| Step::Withdrawal(c, amount)),
+        2 => (client.clone(), target.clone()).prop_map(|(c, index)| Step::Dispute(c, index)),
+        1 => (client.clone(), target.clone()).prop_map(|(c, index)| Step::Resolve(c, index)),
+        1 => (client, target).prop_map(|(c, index)| Step::Chargeback(c, index)),
+    ]
+}
+
+/*
+Turns the steps into transactions. Deposits and withdrawals get the next free id, meta rows
+reference one their client was already given, or the next one.
+*/
+fn transactions(steps: Vec<Step>) -> Vec<Transaction> {
+    let mut ids: Vec<(u16, u32)> = Vec::new();
+    let target = |ids: &[(u16, u32)], client: u16, index: usize| {
+        let own: Vec<u32> = ids
+            .iter()
+            .filter(|(owner, _)| *owner == client)
+            .map(|(_, id)| *id)
+            .collect();
+        match own.get(index % (own.len() + 1)) {
+            Some(&id) => id,
+            None => ids.len() as u32 + 1,
+        }
+    };
+    steps
+        .into_iter()
+        .map(|step| {
+            let next = ids.len() as u32 + 1;
+            match step {
+                Step::Deposit(client, amount) => {
+                    ids.push((client, next));
+                    Transaction::deposit(client, next, amount)
+                }
+                Step::Withdrawal(client, amount) => {
+                    ids.push((client, next));
+                    Transaction::withdrawal(client, next, amount)
+                }
+                Step::Dispute(client, index) => {
+                    Transaction::dispute(client, target(&ids, client, index))
+                }
+                Step::Resolve(client, index) => {
+                    Transaction::resolve(client, target(&ids, client, index))
+                }
+                Step::Chargeback(client, index) => {
+                    Transaction::chargeback(client, target(&ids, client, index))
+                }
+            }
+        })
+        .collect()
+}
+
+/*
+Applies the transactions one at a time, checking after every row, applied or not, that each
+client's balances add up and nothing is held that wasn't deposited. Available only goes
+negative the one way it's allowed to: a dispute holding a deposit the client already spent.
+*/
+fn check_invariants(transactions: Vec<Transaction>) -> Result<(), TestCaseError> {
+    let mut engine = PaymentsEngine::default();
+    for transaction in transactions {
+        let client_id = transaction.client_id;
+        let before = engine.client(client_id).map(|client| client.available());
+        let disputed = match transaction.transaction_type {
+            TransactionType::Dispute => engine
+                .transaction(transaction.transaction_id)
+                .and_then(|target| target.amount),
+            _ => None,
+        };
+
+        engine.apply(transaction.clone()).unwrap();
+
+        for client in engine.clients() {
+            prop_assert!(client.is_consistent(), "{} after {:?}", client, transaction);
+            prop_assert!(
+                client.held() >= Decimal::ZERO,
+                "{} after {:?}",
+                client,
+                transaction
+            );
+        }
+        // a dispute of a tx that doesn't exist never creates its client
+        let Some(client) = engine.client(client_id) else {
+            continue;
+        };
+        if client.available() < Decimal::ZERO {
+            let before = before.unwrap_or_default();
+            let overdrawn_by_dispute = disputed.is_some_and(|amount| amount > before);
+            prop_assert!(
+                before < Decimal::ZERO || overdrawn_by_dispute,
+                "{} after {:?}",
+                client,
+                transaction
+            );
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn balances_stay_consistent_after_every_row(steps in prop::collection::vec(step(), 1..200)) {
+        check_invariants(transactions(steps))?;
+    }
+
+    #[test]
+    fn apply_all_matches_applying_each_row(steps in prop::collection::vec(step(), 1..200)) {
+        let transactions = transactions(steps);
+        let mut one_by_one = PaymentsEngine::default();
+        for transaction in transactions.clone() {
+            one_by_one.apply(transaction).unwrap();
+        }
+        let mut all_at_once = PaymentsEngine::default();
+        all_at_once.apply_all(transactions, ErrorMode::Collect).unwrap();
+
+        let mut expected: Vec<&Client> = one_by_one.clients().collect();
+        let mut applied: Vec<&Client> = all_at_once.clients().collect();
+        expected.sort_by_key(|client| client.id());
+        applied.sort_by_key(|client| client.id());
+        prop_assert_eq!(applied, expected);
+    }
+}
+
+// The orderings random inputs only hit now and then
+#[test]
+fn edge_cases_keep_the_balances_consistent() {
+    let amount = Decimal::new(25, 1);
+    let cases = vec![
+        // disputing, resolving and charging back before the deposit exists
+        vec![
+            Transaction::dispute(1, 1),
+            Transaction::resolve(1, 1),
+            Transaction::chargeback(1, 1),
+            Transaction::deposit(1, 1, amount),
+        ],
+        // spending a deposit and then disputing it
+        vec![
+            Transaction::deposit(1, 1, amount),
+            Transaction::withdrawal(1, 2, amount),
+            Transaction::dispute(1, 1),
+            Transaction::resolve(1, 1),
+        ],
+        // disputing twice, and touching a locked account
+        vec![
+            Transaction::deposit(1, 1, amount),
+            Transaction::dispute(1, 1),
+            Transaction::dispute(1, 1),
+            Transaction::chargeback(1, 1),
+            Transaction::deposit(1, 2, amount),
+            Transaction::resolve(1, 1),
+        ],
+    ];
+    for transactions in cases {
+        check_invariants(transactions).unwrap();
+    }
+}
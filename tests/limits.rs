@@ -0,0 +1,46 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_toy_marketplace"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+const INPUT: &str = "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,1.0
+deposit,3,3,1.0
+";
+
+#[test]
+fn input_with_more_clients_than_the_limit_fails() {
+    let output = run(&["--max-clients", "2"], INPUT);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("transaction 3 would add a client past the limit of 2"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn input_within_the_limits_is_processed_as_usual() {
+    let output = run(&["--max-clients", "3", "--max-transactions", "3"], INPUT);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 4, "{}", stdout);
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::process::Command;
+
+/*
+Deposits for most of the u16 client id space and checks every client comes out once with
+the right balance. Each client gets two deposits, the second one `id / 100` so balances
+differ between clients and a mixed up id would show.
+*/
+#[test]
+fn every_one_of_tens_of_thousands_of_clients_is_written_once() {
+    const CLIENTS: u32 = 60_000;
+
+    let mut input = String::from("type,client,tx,amount\n");
+    for id in 1..=CLIENTS {
+        writeln!(input, "deposit,{},{},1.0", id, id).unwrap();
+    }
+    for id in 1..=CLIENTS {
+        writeln!(input, "deposit,{},{},{}", id, CLIENTS + id, id / 100).unwrap();
+    }
+    let path = std::env::temp_dir().join(format!("many-clients-{}.csv", std::process::id()));
+    std::fs::write(&path, input).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_toy_marketplace"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("client,available,held,total,locked"));
+    let mut balances: HashMap<u32, String> = HashMap::new();
+    for line in lines {
+        let (id, rest) = line.split_once(',').unwrap();
+        let previous = balances.insert(id.parse().unwrap(), rest.to_string());
+        assert_eq!(previous, None, "client {} written twice", id);
+    }
+
+    assert_eq!(balances.len(), CLIENTS as usize);
+    for id in 1..=CLIENTS {
+        let total: f64 = 1.0 + f64::from(id / 100);
+        let mut fields = balances[&id].split(',');
+        let available: f64 = fields.next().unwrap().parse().unwrap();
+        assert_eq!(available, total, "client {}", id);
+        assert_eq!(fields.nth(1).unwrap().parse::<f64>().unwrap(), total);
+        assert_eq!(fields.next(), Some("false"));
+    }
+}
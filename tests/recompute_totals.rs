@@ -0,0 +1,45 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// a total that doesn't match the balances can only come in from outside, like opening balances
+#[test]
+fn drifted_total_is_warned_about_and_written_recomputed() {
+    let balances = std::env::temp_dir().join(format!("drifted-{}.csv", std::process::id()));
+    std::fs::write(
+        &balances,
+        "client,available,held,total,locked\n1,7.5,2.5,12.0,false\n2,1.0,0.0,1.0,false\n",
+    )
+    .unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_toy_marketplace"))
+        .arg("--opening-balances")
+        .arg(&balances)
+        .arg("--recompute-totals")
+        .arg("-v")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // no transactions, only the opening balances are written
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"type,client,tx,amount\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&balances).unwrap();
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.lines().count(), 1);
+    assert!(stderr.contains("WARN"));
+    assert!(stderr.contains("total doesn't match the balances"));
+    assert!(stderr.contains("12.0"));
+    assert!(stderr.contains("10.0"));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,7.5000,2.5000,10.0000,false\n2,1.0000,0.0000,1.0000,false\n"
+    );
+}
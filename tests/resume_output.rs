@@ -0,0 +1,51 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// a client closed during the resumed run belongs in the resumed file, not on stdout
+#[test]
+fn closed_client_is_written_to_the_resumed_output() {
+    let path = std::env::temp_dir().join(format!(
+        "toy_marketplace_resume_closed_{}.csv",
+        std::process::id()
+    ));
+    // cut off part way through client 3's row
+    std::fs::write(
+        &path,
+        "client,available,held,total,locked\n1,10,0,10,false\n3,7,0.",
+    )
+    .unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_toy_marketplace"))
+        .arg("--resume-output")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(
+            b"type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\ndeposit,3,3,7\ncloseclient,2,4,\n",
+        )
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let resumed = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.stdout.is_empty());
+    let mut ids: Vec<&str> = resumed
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    ids.sort_unstable();
+    assert_eq!(ids, vec!["1", "2", "3"]);
+}
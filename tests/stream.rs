@@ -0,0 +1,45 @@
+#![cfg(feature = "async")]
+
+use anyhow::Result;
+use rust_decimal_macros::dec;
+use toy_marketplace::stream::process_stream;
+use toy_marketplace::{PaymentsEngine, Transaction};
+
+#[tokio::test]
+async fn transactions_from_a_stream_are_applied_as_they_arrive() {
+    let transactions: Vec<Result<Transaction>> = vec![
+        Ok(Transaction::deposit(1, 1, dec!(10))),
+        Ok(Transaction::deposit(2, 2, dec!(4.5))),
+        Ok(Transaction::withdrawal(1, 3, dec!(2.5))),
+        Ok(Transaction::dispute(2, 2)),
+        // more than client 1 has, skipped like it would be from a file
+        Ok(Transaction::withdrawal(1, 4, dec!(100))),
+    ];
+    let mut engine = PaymentsEngine::default();
+    process_stream(tokio_stream::iter(transactions), &mut engine)
+        .await
+        .unwrap();
+
+    let client = engine.client(1).unwrap();
+    assert_eq!(client.available(), dec!(7.5));
+    assert_eq!(client.total(), dec!(7.5));
+    let client = engine.client(2).unwrap();
+    assert_eq!(client.available(), dec!(0));
+    assert_eq!(client.held(), dec!(4.5));
+}
+
+#[tokio::test]
+async fn an_error_in_the_stream_stops_it() {
+    let transactions: Vec<Result<Transaction>> = vec![
+        Ok(Transaction::deposit(1, 1, dec!(10))),
+        Err(anyhow::anyhow!("connection reset")),
+        Ok(Transaction::deposit(1, 2, dec!(10))),
+    ];
+    let mut engine = PaymentsEngine::default();
+    let error = process_stream(tokio_stream::iter(transactions), &mut engine)
+        .await
+        .unwrap_err();
+
+    assert_eq!(error.to_string(), "connection reset");
+    assert_eq!(engine.client(1).unwrap().total(), dec!(10));
+}